@@ -1 +1 @@
-(def truthy (fn (x) (if (eq x ()) false true)))
+(def truthy (fn (x) (if x true false)))
@@ -0,0 +1,139 @@
+use super::eval::EvalError;
+
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use std::time::{Duration, Instant};
+
+// limits on how much work a single evaluation is allowed to do
+//
+// a budget is shared by every environment created beneath the one it was
+// installed on, so every reduction step anywhere in the evaluation draws
+// from the same pool
+#[derive(Debug, Default)]
+pub struct Budget {
+    fuel: Option<AtomicU64>,
+    deadline: Option<Instant>,
+    memory: Option<MemoryLimit>,
+    depth: DepthLimit,
+}
+
+// how many evaluations may be nested inside each other
+//
+// every budget has one, since each level of nesting takes host stack that
+// neither fuel nor the memory limit accounts for
+#[derive(Debug)]
+struct DepthLimit {
+    max: usize,
+    current: AtomicUsize,
+}
+
+impl Default for DepthLimit {
+    fn default() -> DepthLimit {
+        DepthLimit {
+            max: Budget::DEFAULT_MAX_DEPTH,
+            current: AtomicUsize::new(0),
+        }
+    }
+}
+
+// one level of nesting, given back when it's dropped
+pub struct Depth<'b>(&'b AtomicUsize);
+
+impl Drop for Depth<'_> {
+    fn drop(&mut self) {
+        self.0.fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
+// a running total of the bytes allocated for lists, symbols and bindings
+//
+// nothing is ever given back when values are dropped, so this caps the total
+// amount allocated over the whole evaluation rather than the peak usage
+#[derive(Debug)]
+struct MemoryLimit {
+    cap: usize,
+    used: AtomicUsize,
+}
+
+impl Budget {
+    pub const DEFAULT_MAX_DEPTH: usize = 10_000;
+
+    pub fn new() -> Budget {
+        Budget::default()
+    }
+
+    // maximum number of reduction steps
+    pub fn with_fuel(mut self, steps: u64) -> Budget {
+        self.fuel = Some(AtomicU64::new(steps));
+        self
+    }
+
+    // wall-clock limit, counted from when this is called
+    pub fn with_timeout(mut self, timeout: Duration) -> Budget {
+        self.deadline = Some(Instant::now() + timeout);
+        self
+    }
+
+    // maximum number of bytes the evaluation may allocate
+    pub fn with_memory_limit(mut self, bytes: usize) -> Budget {
+        self.memory = Some(MemoryLimit {
+            cap: bytes,
+            used: AtomicUsize::new(0),
+        });
+        self
+    }
+
+    // maximum number of evaluations nested inside each other
+    pub fn with_max_depth(mut self, depth: usize) -> Budget {
+        self.depth.max = depth;
+        self
+    }
+
+    pub fn allocated(&self) -> Option<usize> {
+        self.memory.as_ref().map(|m| m.used.load(Ordering::Relaxed))
+    }
+
+    pub fn remaining_fuel(&self) -> Option<u64> {
+        self.fuel.as_ref().map(|f| f.load(Ordering::Relaxed))
+    }
+
+    // take one step out of the budget
+    pub fn step(&self) -> Result<(), EvalError> {
+        if let Some(fuel) = &self.fuel {
+            fuel.fetch_update(Ordering::Relaxed, Ordering::Relaxed, |f| f.checked_sub(1))
+                .map_err(|_| EvalError::OutOfFuel)?;
+        }
+
+        if let Some(deadline) = self.deadline {
+            if Instant::now() >= deadline {
+                return Err(EvalError::DeadlineExceeded);
+            }
+        }
+
+        Ok(())
+    }
+
+    // go one level deeper, until the returned guard is dropped
+    pub fn enter(&self) -> Result<Depth<'_>, EvalError> {
+        let DepthLimit { max, current } = &self.depth;
+        current
+            .fetch_update(Ordering::Relaxed, Ordering::Relaxed, |depth| {
+                Some(depth + 1).filter(|depth| depth <= max)
+            })
+            .map_err(|_| EvalError::TooDeep)?;
+        Ok(Depth(current))
+    }
+
+    // account for an allocation of the given size
+    pub fn allocate(&self, bytes: usize) -> Result<(), EvalError> {
+        if let Some(memory) = &self.memory {
+            memory
+                .used
+                .fetch_update(Ordering::Relaxed, Ordering::Relaxed, |used| {
+                    used.checked_add(bytes).filter(|total| *total <= memory.cap)
+                })
+                .map_err(|_| EvalError::OutOfMemory)?;
+        }
+
+        Ok(())
+    }
+}
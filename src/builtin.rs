@@ -0,0 +1,161 @@
+use super::env::LispEnv;
+use super::eval::EvalError;
+use super::mac::MacroValue;
+use super::value::{ListValue, SymbolValue, Value};
+
+pub type FuncValue = fn(&Args, &LispEnv) -> Result<Value, EvalError>;
+
+// how many arguments a builtin takes
+#[derive(Clone, Copy, Debug)]
+pub enum Arity {
+    Exactly(usize),
+    AtLeast(usize),
+    Between(usize, usize),
+}
+
+impl Arity {
+    fn accepts(&self, count: usize) -> bool {
+        match *self {
+            Arity::Exactly(n) => count == n,
+            Arity::AtLeast(n) => count >= n,
+            Arity::Between(min, max) => (min..=max).contains(&count),
+        }
+    }
+}
+
+impl core::fmt::Display for Arity {
+    fn fmt(&self, f: &mut core::fmt::Formatter) -> core::fmt::Result {
+        let plural = |n: usize| if n == 1 { "argument" } else { "arguments" };
+        match *self {
+            Arity::Exactly(n) => write!(f, "{} {}", n, plural(n)),
+            Arity::AtLeast(n) => write!(f, "at least {} {}", n, plural(n)),
+            Arity::Between(min, max) => write!(f, "{} to {} arguments", min, max),
+        }
+    }
+}
+
+// whether a builtin's arguments are evaluated before it is called
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Evaluation {
+    Strict,
+    // the builtin gets the argument forms as written, and evaluates whichever
+    // of them it wants to itself, like `if` or `let`
+    Lazy,
+}
+
+// a function written in rust
+#[derive(Clone, Copy)]
+pub struct Builtin {
+    pub name: &'static str,
+    pub arity: Arity,
+    pub evaluation: Evaluation,
+    pub func: FuncValue,
+}
+
+impl Builtin {
+    pub fn is_lazy(&self) -> bool {
+        self.evaluation == Evaluation::Lazy
+    }
+
+    pub fn check_arity(&self, count: usize) -> Result<(), EvalError> {
+        if self.arity.accepts(count) {
+            return Ok(());
+        }
+        Err(EvalError::String(format!(
+            "[{}] expected {}, got {}",
+            self.name, self.arity, count
+        )))
+    }
+
+    // `args` are the argument forms of the call
+    pub fn call(&self, args: &[Value], env: &LispEnv) -> Result<Value, EvalError> {
+        self.check_arity(args.len())?;
+        match self.evaluation {
+            Evaluation::Strict => {
+                let values = args
+                    .iter()
+                    .map(|arg| env.eval(arg))
+                    .collect::<Result<Vec<_>, _>>()?;
+                (self.func)(&Args::new(self.name, &values), env)
+            }
+            Evaluation::Lazy => (self.func)(&Args::new(self.name, args), env),
+        }
+    }
+}
+
+// the arguments a builtin was called with, which know whose they are so that
+// errors about them can say so
+pub struct Args<'a> {
+    name: &'static str,
+    values: &'a [Value],
+}
+
+impl<'a> Args<'a> {
+    pub fn new(name: &'static str, values: &'a [Value]) -> Args<'a> {
+        Args { name, values }
+    }
+
+    // an error from this builtin
+    pub fn error(&self, message: impl core::fmt::Display) -> EvalError {
+        EvalError::String(format!("[{}] {}", self.name, message))
+    }
+
+    fn expected(&self, i: usize, kind: &str) -> EvalError {
+        self.error(format!(
+            "expected {} for argument {}, got {}",
+            kind,
+            i + 1,
+            self.values[i]
+        ))
+    }
+
+    pub fn int(&self, i: usize) -> Result<i64, EvalError> {
+        self.values[i]
+            .get_int()
+            .ok_or_else(|| self.expected(i, "an integer"))
+    }
+
+    // a count, or a position in a list
+    pub fn index(&self, i: usize) -> Result<usize, EvalError> {
+        self.values[i]
+            .get_int()
+            .and_then(|n| usize::try_from(n).ok())
+            .ok_or_else(|| self.expected(i, "a non-negative integer"))
+    }
+
+    pub fn ints(&self) -> Result<Vec<i64>, EvalError> {
+        (0..self.values.len()).map(|i| self.int(i)).collect()
+    }
+
+    pub fn bool(&self, i: usize) -> Result<bool, EvalError> {
+        self.values[i]
+            .get_bool()
+            .ok_or_else(|| self.expected(i, "a bool"))
+    }
+
+    pub fn symbol(&self, i: usize) -> Result<SymbolValue, EvalError> {
+        self.values[i]
+            .get_symbol_value()
+            .ok_or_else(|| self.expected(i, "a symbol"))
+    }
+
+    pub fn list(&self, i: usize) -> Result<&'a ListValue, EvalError> {
+        self.values[i]
+            .get_list_value()
+            .ok_or_else(|| self.expected(i, "a list"))
+    }
+
+    pub fn macro_value(&self, i: usize) -> Result<&'a MacroValue, EvalError> {
+        self.values[i]
+            .get_macro()
+            .ok_or_else(|| self.expected(i, "a macro"))
+    }
+}
+
+impl core::ops::Deref for Args<'_> {
+    type Target = [Value];
+
+    fn deref(&self) -> &[Value] {
+        self.values
+    }
+}
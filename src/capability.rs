@@ -0,0 +1,148 @@
+use super::eval::EvalError;
+
+use std::path::{Component, Path, PathBuf};
+
+// the kinds of outside access an unsafe function might need
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Capability {
+    FsRead,
+    FsWrite,
+    Process,
+    Stdin,
+    EnvVars,
+    Time,
+}
+
+impl core::fmt::Display for Capability {
+    fn fmt(&self, f: &mut core::fmt::Formatter) -> core::fmt::Result {
+        use Capability::*;
+        match self {
+            FsRead => write!(f, "fs-read"),
+            FsWrite => write!(f, "fs-write"),
+            Process => write!(f, "process"),
+            Stdin => write!(f, "stdin"),
+            EnvVars => write!(f, "env-vars"),
+            Time => write!(f, "time"),
+        }
+    }
+}
+
+// the set of capabilities granted by the host
+//
+// nothing is granted by default. file access is granted per path prefix, and
+// an empty prefix grants access to every path
+#[derive(Debug, Clone, Default)]
+pub struct Capabilities {
+    read: Vec<PathBuf>,
+    write: Vec<PathBuf>,
+    process: bool,
+    stdin: bool,
+    env_vars: bool,
+    time: bool,
+}
+
+impl Capabilities {
+    pub fn none() -> Capabilities {
+        Capabilities::default()
+    }
+
+    pub fn allow_read(mut self, prefix: impl AsRef<Path>) -> Capabilities {
+        // a prefix that escapes its starting directory can never match anything
+        if let Some(prefix) = normalize(prefix.as_ref()) {
+            self.read.push(prefix);
+        }
+        self
+    }
+
+    pub fn allow_write(mut self, prefix: impl AsRef<Path>) -> Capabilities {
+        if let Some(prefix) = normalize(prefix.as_ref()) {
+            self.write.push(prefix);
+        }
+        self
+    }
+
+    // grant a capability outright. for file access this allows every path
+    pub fn allow(self, capability: Capability) -> Capabilities {
+        use Capability::*;
+        match capability {
+            FsRead => self.allow_read(""),
+            FsWrite => self.allow_write(""),
+            Process => Capabilities {
+                process: true,
+                ..self
+            },
+            Stdin => Capabilities {
+                stdin: true,
+                ..self
+            },
+            EnvVars => Capabilities {
+                env_vars: true,
+                ..self
+            },
+            Time => Capabilities { time: true, ..self },
+        }
+    }
+
+    pub fn has(&self, capability: Capability) -> bool {
+        use Capability::*;
+        match capability {
+            FsRead => !self.read.is_empty(),
+            FsWrite => !self.write.is_empty(),
+            Process => self.process,
+            Stdin => self.stdin,
+            EnvVars => self.env_vars,
+            Time => self.time,
+        }
+    }
+
+    pub fn require(&self, capability: Capability, user: &str) -> Result<(), EvalError> {
+        if self.has(capability) {
+            Ok(())
+        } else {
+            Err(EvalError::MissingCapability(capability, user.to_string()))
+        }
+    }
+
+    pub fn check_read(&self, path: impl AsRef<Path>) -> Result<(), EvalError> {
+        check_path(&self.read, Capability::FsRead, path.as_ref())
+    }
+
+    pub fn check_write(&self, path: impl AsRef<Path>) -> Result<(), EvalError> {
+        check_path(&self.write, Capability::FsWrite, path.as_ref())
+    }
+}
+
+fn check_path(prefixes: &[PathBuf], capability: Capability, path: &Path) -> Result<(), EvalError> {
+    // NOTE: this is purely lexical, symlinks inside an allowed prefix are followed
+    let allowed = match normalize(path) {
+        Some(path) => prefixes.iter().any(|prefix| path.starts_with(prefix)),
+        None => false,
+    };
+
+    if allowed {
+        Ok(())
+    } else {
+        Err(EvalError::MissingCapability(
+            capability,
+            path.display().to_string(),
+        ))
+    }
+}
+
+// resolve `.` and `..` without touching the filesystem
+// paths that climb above where they started are rejected outright
+fn normalize(path: &Path) -> Option<PathBuf> {
+    let mut normal = PathBuf::new();
+    for component in path.components() {
+        match component {
+            Component::CurDir => (),
+            Component::ParentDir => {
+                if !normal.pop() {
+                    return None;
+                }
+            }
+            other => normal.push(other),
+        }
+    }
+    Some(normal)
+}
@@ -2,6 +2,9 @@
 
 use clap::Parser;
 
+use super::budget::Budget;
+use super::capability::{Capabilities, Capability};
+
 #[derive(Parser)]
 #[command(name = "lxsp")]
 //#[command(author = "Nonymous A. <admin@gmail.com>")]
@@ -14,6 +17,98 @@ pub struct ArgStruct {
     pub load: Vec<String>,
     #[arg(long)]
     pub use_old_repl: bool,
+    /// Maximum number of evaluation steps for each line
+    #[arg(long, value_name = "STEPS")]
+    pub fuel: Option<u64>,
+    /// Maximum time in milliseconds to spend evaluating each line
+    #[arg(long, value_name = "MS")]
+    pub timeout: Option<u64>,
+    /// Maximum number of bytes to allocate while evaluating each line
+    #[arg(long, value_name = "BYTES")]
+    pub max_memory: Option<usize>,
+    /// Maximum number of evaluations nested inside each other for each line
+    #[arg(long, value_name = "LEVELS")]
+    pub max_depth: Option<usize>,
+    /// Let unsafe code read files under PREFIX, or any file if no prefix is given
+    #[arg(
+        long,
+        value_name = "PREFIX",
+        num_args = 0..=1,
+        require_equals = true,
+        default_missing_value = ""
+    )]
+    pub allow_read: Vec<String>,
+    /// Let unsafe code write files under PREFIX, or any file if no prefix is given
+    #[arg(
+        long,
+        value_name = "PREFIX",
+        num_args = 0..=1,
+        require_equals = true,
+        default_missing_value = ""
+    )]
+    pub allow_write: Vec<String>,
+    /// Let unsafe code start other processes
+    #[arg(long)]
+    pub allow_process: bool,
+    /// Let unsafe code read from standard input
+    #[arg(long)]
+    pub allow_stdin: bool,
+    /// Let unsafe code read environment variables
+    #[arg(long)]
+    pub allow_env: bool,
+    /// Let unsafe code read the clock
+    #[arg(long)]
+    pub allow_time: bool,
+}
+
+impl ArgStruct {
+    pub fn capabilities(&self) -> Capabilities {
+        let mut capabilities = Capabilities::none();
+        for prefix in &self.allow_read {
+            capabilities = capabilities.allow_read(prefix);
+        }
+        for prefix in &self.allow_write {
+            capabilities = capabilities.allow_write(prefix);
+        }
+        let flags = [
+            (self.allow_process, Capability::Process),
+            (self.allow_stdin, Capability::Stdin),
+            (self.allow_env, Capability::EnvVars),
+            (self.allow_time, Capability::Time),
+        ];
+        for (allowed, capability) in flags {
+            if allowed {
+                capabilities = capabilities.allow(capability);
+            }
+        }
+        capabilities
+    }
+
+    // a fresh budget for one line of input, if any limits were given
+    pub fn budget(&self) -> Option<Budget> {
+        if self.fuel.is_none()
+            && self.timeout.is_none()
+            && self.max_memory.is_none()
+            && self.max_depth.is_none()
+        {
+            return None;
+        }
+
+        let mut budget = Budget::new();
+        if let Some(steps) = self.fuel {
+            budget = budget.with_fuel(steps);
+        }
+        if let Some(ms) = self.timeout {
+            budget = budget.with_timeout(std::time::Duration::from_millis(ms));
+        }
+        if let Some(bytes) = self.max_memory {
+            budget = budget.with_memory_limit(bytes);
+        }
+        if let Some(depth) = self.max_depth {
+            budget = budget.with_max_depth(depth);
+        }
+        Some(budget)
+    }
 }
 
 // TUI //
@@ -1,12 +1,16 @@
-use super::eval::{eval_err, EvalError};
-use super::value::MacroValue;
-use super::value::Value as LispValue;
+use super::budget::{Budget, Depth};
+use super::capability::Capabilities;
+use super::eval::EvalError;
+use super::params::{ParamList, Pattern};
+use super::value::{ListValue, SymbolValue, Value as LispValue};
 
 use std::collections::HashMap;
-use std::error::Error;
 use std::sync::Arc;
 
-pub type Bindings = HashMap<String, LispValue>;
+mod expand;
+mod reduce;
+
+pub type Bindings = HashMap<SymbolValue, LispValue>;
 
 type EvalFn = for<'e, 'v, 'i> fn(&'e LispEnv<'i>, &'v LispValue) -> Result<LispValue, EvalError>;
 
@@ -16,6 +20,8 @@ pub struct LispEnv<'e> {
     outer: Option<&'e LispEnv<'e>>,
     //_outer: Option<&'e LispEnv<'e>>,
     unsafe_level: usize,
+    budget: Option<Arc<Budget>>,
+    capabilities: Arc<Capabilities>,
 }
 
 impl LispEnv<'_> {
@@ -28,12 +34,94 @@ impl LispEnv<'_> {
         outer: Option<&'e LispEnv<'e>>,
         unsafe_level: usize,
     ) -> LispEnv {
+        let budget = outer.and_then(|env| env.budget.clone());
+        let capabilities = match outer {
+            Some(env) => env.capabilities.clone(),
+            None => Default::default(),
+        };
         LispEnv {
             bindings,
             outer,
             //_outer,
             unsafe_level,
+            budget,
+            capabilities,
+        }
+    }
+
+    // a new inner scope where all evaluation draws from the given budget
+    pub fn new_budgeted_env(&self, budget: Budget) -> LispEnv<'_> {
+        let mut env = self.new_inner_from_parts(Default::default(), self.unsafe_level);
+        env.budget = Some(Arc::new(budget));
+        env
+    }
+
+    // a new inner scope where unsafe functions may use exactly these capabilities
+    pub fn new_capable_env(&self, capabilities: Capabilities) -> LispEnv<'_> {
+        let mut env = self.new_inner_from_parts(Default::default(), self.unsafe_level);
+        env.capabilities = Arc::new(capabilities);
+        env
+    }
+
+    pub fn capabilities(&self) -> &Capabilities {
+        &self.capabilities
+    }
+
+    pub fn budget(&self) -> Option<&Budget> {
+        self.budget.as_deref()
+    }
+
+    fn step(&self) -> Result<(), EvalError> {
+        match &self.budget {
+            Some(budget) => budget.step(),
+            None => Ok(()),
+        }
+    }
+
+    // the host stack needed for evaluations nested deeper than this grows onto
+    // the heap, so that how deep they can go is up to the budget instead
+    const STACK_RED_ZONE: usize = 256 * 1024;
+    const STACK_SEGMENT: usize = 4 * 1024 * 1024;
+
+    fn enter(&self) -> Result<Option<Depth<'_>>, EvalError> {
+        self.budget.as_deref().map(Budget::enter).transpose()
+    }
+
+    fn allocate(&self, bytes: usize) -> Result<(), EvalError> {
+        match &self.budget {
+            Some(budget) => budget.allocate(bytes),
+            None => Ok(()),
+        }
+    }
+
+    // charge the budget for building a list with this many elements
+    pub fn charge_list(&self, len: usize) -> Result<(), EvalError> {
+        self.allocate(len.saturating_mul(std::mem::size_of::<LispValue>()))
+    }
+
+    // the elements of a list side by side, charging the budget for the copy
+    // that a consed list needs the first time
+    pub fn slice<'l>(&self, list: &'l ListValue) -> Result<&'l [LispValue], EvalError> {
+        if !list.is_flat() {
+            self.charge_list(list.len())?;
         }
+        Ok(list)
+    }
+
+    // charge the budget for consing one element onto a list
+    pub fn charge_cell(&self) -> Result<(), EvalError> {
+        self.allocate(ListValue::CELL_SIZE)
+    }
+
+    // charge the budget for a value built from outside data, such as a parsed file
+    pub fn charge_value(&self, val: &LispValue) -> Result<(), EvalError> {
+        self.allocate(std::mem::size_of::<LispValue>() + val.heap_size())
+    }
+
+    // charge the budget for a new table of bindings
+    pub fn charge_bindings(&self, bindings: &Bindings) -> Result<(), EvalError> {
+        let entry = std::mem::size_of::<(SymbolValue, LispValue)>();
+        self.allocate(bindings.len().saturating_mul(entry))
     }
 
     pub fn new_inner_from_parts(&self, bindings: Bindings, unsafe_level: usize) -> LispEnv {
@@ -44,24 +132,26 @@ impl LispEnv<'_> {
         LispEnv::new(bindings, None, 0)
     }
 
-    pub fn get(&self, s: &String) -> Option<&LispValue> {
-        match self.bindings.get(s) {
+    pub fn get(&self, s: &str) -> Option<&LispValue> {
+        self.lookup(&SymbolValue::find(s)?)
+    }
+
+    pub fn lookup(&self, sym: &SymbolValue) -> Option<&LispValue> {
+        match self.bindings.get(sym) {
             Some(e) => Some(e),
             None => match &self.outer {
-                Some(env) => env.get(s),
+                Some(env) => env.lookup(sym),
                 None => None,
             },
         }
     }
 
-    pub fn list<'a>(
-        &'a self,
-    ) -> std::collections::hash_map::Keys<'a, std::string::String, LispValue> {
+    pub fn list<'a>(&'a self) -> std::collections::hash_map::Keys<'a, SymbolValue, LispValue> {
         self.bindings.keys()
     }
 
-    pub fn sorted_list<'a>(&'a self) -> Vec<&'a String> {
-        let mut v: Vec<_> = self.list().collect();
+    pub fn sorted_list<'a>(&'a self) -> Vec<&'a str> {
+        let mut v: Vec<_> = self.list().map(SymbolValue::as_str).collect();
 
         v.sort();
 
@@ -89,13 +179,14 @@ impl LispEnv<'_> {
     pub fn delegated_eval(&self, val: &LispValue, eval_fn: EvalFn) -> Result<LispValue, EvalError> {
         use LispValue::*;
 
+        self.step()?;
+
         Ok(match val {
-            Bool(_) | Integer(_) | Macro(_) | Func(_) | UnsafeFunc(_) | Lambda(_) => {
-                val.fallible_clone()?
-            }
-            UnsafeCall(_) => todo!(),
+            Bool(_) | Integer(_) | Macro(_) | Func(_) | UnsafeFunc(_) | Lambda(_)
+            | UnsafeCall(_) => val.fallible_clone()?,
+            Symbol(_) if val.is_keyword() => val.clone(),
             Symbol(s) => self
-                .get(s)
+                .lookup(s)
                 .ok_or(EvalError::String(format!(
                     "[internal eval] use of undeclared variable {}",
                     &s
@@ -105,14 +196,12 @@ impl LispEnv<'_> {
                 if val.is_nil() {
                     val.fallible_clone()?
                 } else {
-                    let f = eval_fn(self, &list[0])?;
-                    //self.apply(&f, &list[1..])?
-                    match f {
-                        //Macro(_) => eval_fn(self, val)?,
-                        //Macro(_) => panic!("macros should be gone"),
-                        Macro(_) => LispValue::quoted("1998".into()),
-                        _ => self.outer_apply(&f, &list[1..])?,
-                    }
+                    let _depth = self.enter()?;
+                    let list = self.slice(list)?;
+                    stacker::maybe_grow(LispEnv::STACK_RED_ZONE, LispEnv::STACK_SEGMENT, || {
+                        let f = eval_fn(self, &list[0])?;
+                        self.outer_apply(&f, &list[1..])
+                    })?
                 }
             }
         })
@@ -122,8 +211,7 @@ impl LispEnv<'_> {
         use LispValue::*;
 
         match val {
-            Macro(_) => todo!("Macro expansion at runtime"),
-            Lambda(_) | Func(_) | UnsafeFunc(_) => self.apply(val, args),
+            Macro(_) | Lambda(_) | Func(_) | UnsafeFunc(_) => self.apply(val, args),
             //~ UnsafeFunc(_) => UnsafeCall(list.clone()),
             Bool(_) | Integer(_) | Symbol(_) | List(_) | UnsafeCall(_) => {
                 return Err(EvalError::String(format!(
@@ -138,154 +226,49 @@ impl LispEnv<'_> {
         env.delegated_eval(val, LispEnv::eval_)
     }
 
-    fn macro_eval(&self, val: &LispValue) -> Result<LispValue, EvalError> {
-        LispEnv::_macro_eval(self, val)
-    }
-
-    /*
-        fn _macro_expand(&self, mac: &MacroValue, args: &[LispValue]) -> Result<LispValue, EvalError> {
-            let inner_env = self.new_inner_env(LispValue::List(mac.params.clone()).into(), args)?;
-            let body = (*mac.body).clone();
-            Ok(inner_env.macro_eval(&body)?)
-        }
-    */
-    fn macro_expand(&self, _mac: &MacroValue, _args: &[LispValue]) -> Result<LispValue, EvalError> {
-        todo!()
-    }
-
-    fn _macro_find<'a>(
-        mac: &MacroValue,
-        args: &'a [LispValue],
-        pattern: &LispValue,
-    ) -> Option<&'a LispValue> {
-        if let Some(index) = mac.params.iter().position(|s| s == pattern) {
-            Some(&args[index])
-        } else {
-            None
-        }
-    }
-
-    fn _macro_replacement_recurse(
-        value: &LispValue,
-        _mac: &MacroValue,
-        _args: &[LispValue],
-        _pattern: &LispValue,
-    ) -> Result<LispValue, Box<dyn Error>> {
-        use LispValue::*;
-
-        fn _fallible_replace(_v: &LispValue) -> Result<LispValue, Box<dyn Error>> {
-            todo!() //Ok(LispEnv::macro_replacement_recurse(v, mac, args, pattern)?)
-        }
-
-        match value {
-            Macro(_) => todo!("macros cannot currently produce their own macros"),
-            List(l) => {
-                let list = (*l)
-                    .iter()
-                    .map(_fallible_replace)
-                    .collect::<Result<Vec<LispValue>, Box<dyn Error>>>()?;
-
-                Ok(List(list.into()))
-            }
-
-            Symbol(_s) => {
-                /*
-                let v = match LispEnv::macro_find(mac, args, pattern) {
-                    Some(other) => other,
-                    None => value,
-                };
-                Ok(v.clone())
-                */
-                Ok(value.clone())
-            }
-
-            Bool(_) | Integer(_) | Func(_) | UnsafeFunc(_) | Lambda(_) | UnsafeCall(_) => {
-                Ok(value.clone())
-            }
-        }
-    }
-
-    fn _macro_eval(env: &LispEnv, val: &LispValue) -> Result<LispValue, EvalError> {
-        use LispValue::*;
-
-        match val {
-            //Macro(_mac) => Ok(val.clone()),
-            LispValue::List(list) => {
-                let f = env.delegated_eval(&list[0], LispEnv::_macro_eval)?;
-                match f {
-                    Macro(mac) => Ok(env.macro_expand(&mac, &list[1..])?),
-                    _ => Ok(env.outer_apply(&f, &list[1..])?),
-                }
-            }
-            _ => env.delegated_eval(val, LispEnv::_macro_eval),
-        }
-    }
-
-    /*
-    fn _reduce1() {
-        let env = LispEnv::empty();
-        let _ = env.reduce(&LispValue::nil());
-        todo!()
-    }
-    */
-
-    // TODO: change the things that can't be reduced to instead return LispValue::Partial
-    pub fn reduce(&self, val: &LispValue) -> Result<LispValue, EvalError> {
-        use LispValue::*;
-
-        Ok(match val {
-            Bool(_) | Integer(_) | Macro(_) | Func(_) | UnsafeFunc(_) | Lambda(_) => {
-                val.fallible_clone()?
-            }
-            UnsafeCall(_) => todo!(),
-            Symbol(s) => {
-                let maybe_env_value = self.get(s);
-                match maybe_env_value {
-                    Some(env_value) => env_value.fallible_clone()?,
-                    None => val.fallible_clone()?,
-                }
-            }
-            List(list) => {
-                if val.is_nil() {
-                    val.fallible_clone()?
-                } else {
-                    let f = self.reduce(&list[0])?;
-                    match f {
-                        Macro(_) | Func(_) | Lambda(_) => self.apply(&f, &list[1..])?,
-                        UnsafeFunc(_) => val.fallible_clone()?,
-                        UnsafeCall(_) => todo!(),
-                        Bool(_) | Integer(_) | Symbol(_) | List(_) => {
-                            return Err(EvalError::String(format!(
-                                "[internal fn: reduce] value cannot be called: {}",
-                                &f
-                            )))
-                        }
-                    }
-                }
-            }
-        })
+    // call a function with values that have already been evaluated, which are
+    // quoted so that they aren't evaluated again
+    pub fn call(&self, f: &LispValue, values: &[LispValue]) -> Result<LispValue, EvalError> {
+        let quoted = values
+            .iter()
+            .cloned()
+            .map(LispValue::quoted)
+            .collect::<Vec<_>>();
+        self.apply(f, &quoted)
     }
 
-    fn apply(&self, val: &LispValue, args: &[LispValue]) -> Result<LispValue, EvalError> {
+    pub fn apply(&self, val: &LispValue, args: &[LispValue]) -> Result<LispValue, EvalError> {
         use LispValue::*;
 
         match val {
-            Func(func) => func(args, self),
+            Func(builtin) => builtin.call(args, self),
             UnsafeFunc(func) => {
+                // outside of `unsafe` the call is only described, to be run later
+                // with its arguments evaluated now, unless it reads them as written
                 if self.unsafe_level == 0 {
-                    return Err(eval_err(
-                        "[internal fn: apply] attempt to call unsafe function without `unsafe`",
-                    ));
+                    func.builtin.check_arity(args.len())?;
+                    let args = if func.builtin.is_lazy() {
+                        args.to_vec()
+                    } else {
+                        self.eval_forms(args)?
+                    };
+                    return Ok(LispValue::unsafe_call(val, args));
+                }
+                for capability in func.capabilities {
+                    self.capabilities.require(*capability, func.builtin.name)?;
                 }
-                func(args, self)
+                func.builtin.call(args, self)
             }
             Lambda(lambda) => {
-                let inner_env = self.new_inner_env(lambda.args.clone(), args)?;
-                let closure_env =
-                    inner_env.new_inner_from_parts(lambda.closure.clone(), self.unsafe_level);
-                closure_env.eval(&lambda.body)
+                let args = self.eval_forms(args)?;
+                let (params, body) = lambda.clause(args.len())?;
+                // the params are bound on top of the closure, so they shadow it
+                let closure_env = self.new_inner_from_parts(lambda.scope(), self.unsafe_level);
+                let inner_env = closure_env.new_param_env(params, args)?;
+                inner_env.eval(body)
             }
-            Macro(_) => todo!("runtime macro expansion"),
+            // the expansion is evaluated in the caller's environment
+            Macro(mac) => self.eval(&self.macro_expand(mac, args)?),
             Bool(_) | Integer(_) | Symbol(_) | List(_) | UnsafeCall(_) => Err(EvalError::String(
                 format!("cannot apply {}; not a function", &val),
             )),
@@ -296,64 +279,40 @@ impl LispEnv<'_> {
         self.new_inner_from_parts(Default::default(), self.unsafe_level + 1)
     }
 
-    pub fn new_macro_env<'a>(
+    // bind already evaluated arguments, or the unevaluated argument forms of a
+    // macro call, to a parameter list. defaults are evaluated in this scope
+    pub fn new_param_env<'a>(
         &'a self,
-        params: Arc<[LispValue]>,
-        args: &[LispValue],
+        params: ParamList,
+        args: Vec<LispValue>,
     ) -> Result<LispEnv<'a>, EvalError> {
-        let id =
-            |_env: &LispEnv, val: &LispValue| -> Result<LispValue, EvalError> { Ok(val.clone()) };
-        self.new_custom_env(params, args, id, id, self.unsafe_level)
-        //todo!()
+        let data = params.bind(args, |default, bound| {
+            self.charge_bindings(bound)?;
+            self.new_inner_from_parts(bound.clone(), self.unsafe_level)
+                .eval(default)
+        })?;
+        self.charge_bindings(&data)?;
+        Ok(self.new_inner_from_parts(data, self.unsafe_level))
     }
 
-    pub fn new_custom_env<'a>(
-        &'a self,
-        params: Arc<[LispValue]>,
-        args: &[LispValue],
-        param_parse: EvalFn,
-        arg_parse: EvalFn,
-        unsafe_level: usize,
-    ) -> Result<LispEnv<'a>, EvalError> {
-        let p_len = params.len();
-        let a_len = args.len();
-        if p_len != a_len {
-            return Err(EvalError::String(format!(
-                "macro expected {} arguments, got {}",
-                p_len, a_len
-            )));
-        }
-
-        let keys = params.iter().map(|x| param_parse(self, x));
-        let values = args.iter().map(|x| arg_parse(self, x));
-
-        let mut data: Bindings = HashMap::new();
-
-        for (key, value) in keys.zip(values) {
-            data.insert(key?.to_string(), value?.clone());
-        }
-        Ok(self.new_inner_from_parts(data, unsafe_level))
+    // bind the names a `match` pattern picked out
+    pub fn new_matched_env<'a>(&'a self, data: Bindings) -> Result<LispEnv<'a>, EvalError> {
+        self.charge_bindings(&data)?;
+        Ok(self.new_inner_from_parts(data, self.unsafe_level))
     }
 
-    pub fn new_inner_env<'a>(
+    // bind names, or patterns, to values that have already been evaluated
+    pub fn new_bound_env<'a>(
         &'a self,
-        params: Arc<LispValue>,
-        arg_forms: &[LispValue],
+        patterns: Vec<Pattern>,
+        values: Vec<LispValue>,
     ) -> Result<LispEnv<'a>, EvalError> {
-        let ks = parse_list_of_symbol_strings(params)?;
-        if ks.len() != arg_forms.len() {
-            return Err(EvalError::String(format!(
-                "lambda call expected {} arguments, got {}",
-                ks.len(),
-                arg_forms.len()
-            )));
-        }
-        let vs = self.eval_forms(arg_forms)?;
-        let mut data: Bindings = HashMap::new();
-        for (k, v) in ks.iter().zip(vs.iter()) {
-            data.insert(k.clone(), v.clone());
+        let mut data = Bindings::new();
+        for (pattern, value) in patterns.iter().zip(values) {
+            pattern.bind(value, &mut data)?;
         }
-        Ok(self.new_inner_from_parts(data, 0))
+        self.charge_bindings(&data)?;
+        Ok(self.new_inner_from_parts(data, self.unsafe_level))
     }
 
     fn eval_forms(&self, arg_forms: &[LispValue]) -> Result<Vec<LispValue>, EvalError> {
@@ -375,7 +334,9 @@ impl LispEnv<'_> {
                                 return Err(format!("[new_inner_from_pairs] not a list of pairs"));
                             }
                             // TODO: impl from/into for the error type and change this to not panic
-                            names.push(binding[0].clone());
+                            let pattern = Pattern::parse(&binding[0])
+                                .map_err(|e| format!("[new_inner_from_pairs] {}", e))?;
+                            names.push(pattern);
                             values.push(self.eval(&binding[1]).unwrap());
                         }
                         _ => {
@@ -387,10 +348,8 @@ impl LispEnv<'_> {
             _ => return Err(format!("[new_inner_from_pairs] not a list")),
         }
 
-        // TODO: impl from/into for the error type and change this to not panic
-        Ok(self
-            .new_inner_env(Arc::new(LispValue::List(names.into())), &values)
-            .unwrap())
+        self.new_bound_env(names, values)
+            .map_err(|e| format!("[new_inner_from_pairs] {}", e))
     }
 }
 
@@ -399,17 +358,3 @@ impl Default for LispEnv<'static> {
         crate::eval::default_env()
     }
 }
-
-// TODO: investigate if this should take ListValue as an argument
-fn parse_list_of_symbol_strings(form: Arc<LispValue>) -> Result<Vec<String>, EvalError> {
-    let list = match form.as_ref() {
-        LispValue::List(s) => Ok(s.clone()),
-        _ => Err(EvalError::Static("expected args form to be a list")),
-    }?;
-    list.iter()
-        .map(|x| match x {
-            LispValue::Symbol(s) => Ok(s.clone()),
-            _ => Err(EvalError::Static("expected symbols in the argument list")),
-        })
-        .collect()
-}
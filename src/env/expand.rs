@@ -0,0 +1,525 @@
+// Macro expansion
+//
+// the expansion pass walks a form before it is evaluated and replaces every
+// macro call with its expansion, looking macros up in the environment the form
+// will run in. anything that isn't expanded here is still expanded when it is
+// evaluated
+//
+// expansions are hygienic: any `fn` param or `let` name that a macro comes up
+// with by itself is renamed to a fresh symbol, along with the symbols the
+// macro came up with that refer to it. symbols passed in by the caller are told
+// apart by their sites, since they are the same symbols the caller wrote
+
+use super::LispEnv;
+use crate::eval::EvalError;
+use crate::mac::gensym;
+use crate::matching::{self, MatchPattern};
+use crate::params::{self, let_pairs, FnForm, ParamList, Pattern, DEF, DO};
+use crate::value::{MacroValue, SymbolValue, Value as LispValue};
+
+use std::collections::{HashMap, HashSet};
+
+// how many expansions may be nested inside each other before the rest is left
+// to be expanded at runtime. recursive macros only stop where a condition
+// that isn't known until then says so
+const EXPANSION_DEPTH: usize = 32;
+
+impl LispEnv<'_> {
+    // the macro body is evaluated with its params bound to the unevaluated
+    // argument forms, and whatever it evaluates to is the expansion
+    pub fn macro_expand(
+        &self,
+        mac: &MacroValue,
+        args: &[LispValue],
+    ) -> Result<LispValue, EvalError> {
+        let expansion = match mac {
+            MacroValue::Procedural { params, body } => {
+                let params = ParamList::parse(&LispValue::List(params.clone()))?;
+                self.new_param_env(params, args.to_vec())?.eval(body)?
+            }
+            MacroValue::Rules(rules) => rules.expand(args)?,
+        };
+
+        let mut from_caller = HashSet::new();
+        for arg in args {
+            symbol_ids(arg, &mut from_caller);
+        }
+        Ok(self.rename_introduced(&expansion, &from_caller, &HashMap::new()))
+    }
+
+    pub fn macro_eval(&self, val: &LispValue) -> Result<LispValue, EvalError> {
+        self.expand_in(val, &HashSet::new(), 0)
+    }
+
+    // expand the form once if it is a macro call, and say whether it was
+    pub fn macro_expand_1(&self, val: &LispValue) -> Result<(LispValue, bool), EvalError> {
+        let list = match val {
+            LispValue::List(list) if !list.is_empty() => list,
+            _ => return Ok((val.clone(), false)),
+        };
+
+        match self.macro_in_head(&list[0], &HashSet::new())? {
+            Some(mac) => Ok((self.macro_expand(&mac, &list[1..])?, true)),
+            None => Ok((val.clone(), false)),
+        }
+    }
+
+    // `local` holds the names bound by an enclosing `fn` or `let`, which hide
+    // any macro of the same name
+    fn expand_in(
+        &self,
+        val: &LispValue,
+        local: &HashSet<SymbolValue>,
+        depth: usize,
+    ) -> Result<LispValue, EvalError> {
+        use LispValue::*;
+
+        let list = match val {
+            List(list) if !list.is_empty() && depth < EXPANSION_DEPTH => list,
+            _ => return Ok(val.clone()),
+        };
+
+        // quoted data is never expanded
+        if self.is_builtin(&list[0], "quote") {
+            return Ok(val.clone());
+        }
+
+        // the head might itself expand to a macro
+        let head = self.expand_in(&list[0], local, depth)?;
+
+        if let Some(mac) = self.macro_in_head(&head, local)? {
+            self.step()?;
+            return match self.macro_expand(&mac, &list[1..]) {
+                // keep going until what's left isn't a macro call
+                Ok(expansion) => self.expand_in(&expansion, local, depth + 1),
+                Err(e) if e.is_exhausted() => Err(e),
+                // the call might never be reached, so any error is left for runtime
+                Err(_) => Ok(val.clone()),
+            };
+        }
+
+        let mut new_list = vec![head];
+        let mut local = local.clone();
+
+        // parameter lists are names, not code
+        let rest = match &list[1..] {
+            args if self.is_builtin(&list[0], "fn") && FnForm::parse(args).is_ok() => {
+                let form = FnForm::parse(args).unwrap();
+                let mut local = local.clone();
+                local.extend(form.name.and_then(LispValue::get_symbol_value));
+                let mut clauses = vec![];
+                for (params, body) in form.clauses.iter() {
+                    let mut local = local.clone();
+                    if let Ok(params) = ParamList::parse(params) {
+                        local.extend(params.names());
+                    }
+                    clauses.push(((*params).clone(), self.expand_in(body, &local, depth)?));
+                }
+                return Ok(form.rebuild(new_list.remove(0), form.name.cloned(), clauses));
+            }
+            [params, rest @ ..] if self.is_builtin(&list[0], "macro") => {
+                if let Ok(params) = ParamList::parse(params) {
+                    local.extend(params.names());
+                }
+                new_list.push(params.clone());
+                rest
+            }
+            [bindings, ..] if self.let_form(&list[0]).is_some() => {
+                local.extend(let_names(bindings));
+                &list[1..]
+            }
+            // a definition binds its name for the forms after it
+            forms if self.is_builtin(&list[0], DO) => {
+                for form in forms {
+                    match form.get_list() {
+                        Some([head, pattern, _]) if self.is_builtin(head, DEF) => {
+                            if let Ok(pattern) = Pattern::parse(pattern) {
+                                local.extend(pattern.names());
+                            }
+                        }
+                        _ => (),
+                    }
+                    new_list.push(self.expand_in(form, &local, depth)?);
+                }
+                return Ok(List(new_list.into()));
+            }
+            // each clause's pattern binds names for its result only
+            [subject, clauses @ ..] if self.is_builtin(&list[0], "match") => {
+                new_list.push(self.expand_in(subject, &local, depth)?);
+                for clause in clauses {
+                    new_list.push(match clause.get_list() {
+                        Some([pattern, body]) => {
+                            let mut local = local.clone();
+                            if let Ok(pattern) = MatchPattern::parse(pattern) {
+                                local.extend(pattern.names());
+                            }
+                            let body = self.expand_in(body, &local, depth)?;
+                            List(vec![pattern.clone(), body].into())
+                        }
+                        _ => clause.clone(),
+                    });
+                }
+                return Ok(List(new_list.into()));
+            }
+            rest => rest,
+        };
+
+        for v in rest.iter() {
+            new_list.push(self.expand_in(v, &local, depth)?);
+        }
+
+        Ok(List(new_list.into()))
+    }
+
+    // the macro a call head refers to, if any
+    fn macro_in_head(
+        &self,
+        head: &LispValue,
+        local: &HashSet<SymbolValue>,
+    ) -> Result<Option<MacroValue>, EvalError> {
+        use LispValue::*;
+
+        Ok(match head {
+            Macro(mac) => Some(mac.clone()),
+            Symbol(s) if local.contains(s) => None,
+            Symbol(s) => match self.lookup(s) {
+                Some(Macro(mac)) => Some(mac.clone()),
+                _ => None,
+            },
+            // a macro made on the spot, possibly by another macro
+            List(list) if !list.is_empty() && self.is_builtin(&list[0], "macro") => {
+                match self.eval(head)? {
+                    Macro(mac) => Some(mac),
+                    _ => None,
+                }
+            }
+            _ => None,
+        })
+    }
+
+    // `renames` maps the names of binders the macro introduced to their new names
+    fn rename_introduced(
+        &self,
+        val: &LispValue,
+        from_caller: &HashSet<u64>,
+        renames: &HashMap<SymbolValue, LispValue>,
+    ) -> LispValue {
+        use LispValue::*;
+
+        let list = match val {
+            Symbol(s) if !from_caller.contains(&s.site()) => {
+                return renames.get(s).unwrap_or(val).clone();
+            }
+            List(list) if !list.is_empty() => list,
+            _ => return val.clone(),
+        };
+
+        let rename_all = |vals: &[LispValue], renames: &HashMap<SymbolValue, LispValue>| {
+            vals.iter()
+                .map(|v| self.rename_introduced(v, from_caller, renames))
+                .collect::<Vec<_>>()
+        };
+
+        let head = &list[0];
+
+        if self.is_builtin(head, "quote") {
+            return val.clone();
+        }
+
+        match &list[1..] {
+            args if self.is_builtin(head, "fn") && FnForm::parse(args).is_ok() => {
+                let form = FnForm::parse(args).unwrap();
+                let mut inner = renames.clone();
+                let name = form.name.map(|name| bind(name, from_caller, &mut inner));
+                let clauses = form
+                    .clauses
+                    .iter()
+                    .map(|(params, body)| {
+                        let mut inner = inner.clone();
+                        let params = self.bind_params(params, from_caller, &mut inner);
+                        (params, self.rename_introduced(body, from_caller, &inner))
+                    })
+                    .collect();
+                form.rebuild(head.clone(), name, clauses)
+            }
+            [params, body @ ..] if self.is_builtin(head, "macro") => {
+                let mut inner = renames.clone();
+                let params = self.bind_params(params, from_caller, &mut inner);
+                let mut new_list = vec![head.clone(), params];
+                new_list.extend(rename_all(body, &inner));
+                List(new_list.into())
+            }
+            [bindings, body @ ..]
+                if self.let_form(head).is_some() && let_pairs(bindings).is_some() =>
+            {
+                let kind = self.let_form(head).unwrap();
+                let pairs = let_pairs(bindings).unwrap();
+
+                let mut inner = renames.clone();
+                // a `letrec`'s values see all of its names
+                if kind == "letrec" {
+                    for pair in pairs {
+                        if let Some([name, _]) = pair.get_list() {
+                            bind(name, from_caller, &mut inner);
+                        }
+                    }
+                }
+                let mut new_pairs = vec![];
+                for pair in pairs {
+                    new_pairs.push(match pair.get_list() {
+                        Some([name, value]) => {
+                            let (name, value) = match kind {
+                                // the values are evaluated outside of a `let`
+                                "let" => {
+                                    let value = self.rename_introduced(value, from_caller, renames);
+                                    (bind(name, from_caller, &mut inner), value)
+                                }
+                                // and after the names before them in a `let*`
+                                "let*" => {
+                                    let value = self.rename_introduced(value, from_caller, &inner);
+                                    (bind(name, from_caller, &mut inner), value)
+                                }
+                                _ => (
+                                    self.rename_introduced(name, from_caller, &inner),
+                                    self.rename_introduced(value, from_caller, &inner),
+                                ),
+                            };
+                            List(vec![name, value].into())
+                        }
+                        _ => pair.clone(),
+                    });
+                }
+
+                let bindings = match quoted_pairs(bindings) {
+                    Some((quote, _)) => List(vec![quote.clone(), List(new_pairs.into())].into()),
+                    None => List(new_pairs.into()),
+                };
+                let mut new_list = vec![head.clone(), bindings];
+                new_list.extend(rename_all(body, &inner));
+                List(new_list.into())
+            }
+            // definitions are seen by the whole body, like a `letrec`'s names
+            forms if self.is_builtin(head, DO) => {
+                let mut inner = renames.clone();
+                for form in forms {
+                    match form.get_list() {
+                        Some([def, name, _]) if self.is_builtin(def, DEF) => {
+                            bind(name, from_caller, &mut inner);
+                        }
+                        _ => (),
+                    }
+                }
+                let mut new_list = vec![head.clone()];
+                new_list.extend(rename_all(forms, &inner));
+                List(new_list.into())
+            }
+            [subject, clauses @ ..] if self.is_builtin(head, "match") => {
+                let mut new_list = vec![
+                    head.clone(),
+                    self.rename_introduced(subject, from_caller, renames),
+                ];
+                for clause in clauses {
+                    new_list.push(match clause.get_list() {
+                        Some([pattern, body]) => {
+                            let mut inner = renames.clone();
+                            let pattern =
+                                self.bind_match(pattern, from_caller, renames, &mut inner);
+                            let body = self.rename_introduced(body, from_caller, &inner);
+                            List(vec![pattern, body].into())
+                        }
+                        _ => self.rename_introduced(clause, from_caller, renames),
+                    });
+                }
+                List(new_list.into())
+            }
+            _ => List(rename_all(list, renames).into()),
+        }
+    }
+
+    // like `bind`, for a params list. patterns come first, and `(name default)`
+    // after the markers that start the optional and keyword params
+    fn bind_params(
+        &self,
+        params: &LispValue,
+        from_caller: &HashSet<u64>,
+        renames: &mut HashMap<SymbolValue, LispValue>,
+    ) -> LispValue {
+        use LispValue::*;
+
+        let params = match params {
+            List(params) => params,
+            _ => return params.clone(),
+        };
+
+        let (mut defaults, mut keys) = (false, false);
+        let params = params
+            .iter()
+            .map(|p| match p.get_list() {
+                _ if p.get_symbol() == Some(params::OPTIONAL) => {
+                    defaults = true;
+                    p.clone()
+                }
+                _ if p.get_symbol() == Some(params::KEY) => {
+                    (defaults, keys) = (true, true);
+                    p.clone()
+                }
+                // a default can refer to the params before it
+                Some([name, default]) if keys => {
+                    let default = self.rename_introduced(default, from_caller, renames);
+                    bind_key(name, Some(default), from_caller, renames)
+                }
+                Some([name, default]) if defaults => {
+                    let default = self.rename_introduced(default, from_caller, renames);
+                    List(vec![bind(name, from_caller, renames), default].into())
+                }
+                Some([name]) if keys => bind_key(name, None, from_caller, renames),
+                _ if keys => bind_key(p, None, from_caller, renames),
+                _ => bind(p, from_caller, renames),
+            })
+            .collect::<Vec<_>>();
+        List(params.into())
+    }
+
+    // like `bind`, for a `match` pattern. predicates are code that runs outside
+    // of the clause, and quoted data and literals are left alone
+    fn bind_match(
+        &self,
+        pattern: &LispValue,
+        from_caller: &HashSet<u64>,
+        outer: &HashMap<SymbolValue, LispValue>,
+        renames: &mut HashMap<SymbolValue, LispValue>,
+    ) -> LispValue {
+        use LispValue::*;
+
+        match pattern {
+            Symbol(_) if matches!(MatchPattern::parse(pattern), Ok(MatchPattern::Bind(_))) => {
+                bind(pattern, from_caller, renames)
+            }
+            List(list) => match &list[..] {
+                [quote, _] if quote.get_symbol() == Some("quote") => pattern.clone(),
+                [guard, pred, rest @ ..] if guard.get_symbol() == Some(matching::GUARD) => {
+                    let mut new_list = vec![
+                        guard.clone(),
+                        self.rename_introduced(pred, from_caller, outer),
+                    ];
+                    new_list.extend(
+                        rest.iter()
+                            .map(|p| self.bind_match(p, from_caller, outer, renames)),
+                    );
+                    List(new_list.into())
+                }
+                _ => List(
+                    list.iter()
+                        .map(|p| self.bind_match(p, from_caller, outer, renames))
+                        .collect::<Vec<_>>()
+                        .into(),
+                ),
+            },
+            _ => pattern.clone(),
+        }
+    }
+
+    // which of the `let`s a form is, if any
+    fn let_form(&self, head: &LispValue) -> Option<&'static str> {
+        ["let", "let*", "letrec"]
+            .into_iter()
+            .find(|name| self.is_builtin(head, name))
+    }
+
+    // whether the head of a form is the builtin of that name, under any name
+    pub fn is_builtin(&self, head: &LispValue, name: &str) -> bool {
+        let builtin = match head {
+            LispValue::Symbol(s) => self.lookup(s),
+            _ => Some(head),
+        };
+        matches!(builtin, Some(LispValue::Func(builtin)) if builtin.name == name)
+    }
+}
+
+// binds the names in a param or pattern for whatever comes after it, renaming
+// the ones the macro introduced
+fn bind(
+    pattern: &LispValue,
+    from_caller: &HashSet<u64>,
+    renames: &mut HashMap<SymbolValue, LispValue>,
+) -> LispValue {
+    use LispValue::*;
+
+    match pattern {
+        _ if params::is_marker(pattern) || pattern.is_keyword() => pattern.clone(),
+        Symbol(s) if !from_caller.contains(&s.site()) => {
+            let fresh = gensym(s);
+            renames.insert(*s, fresh.clone());
+            fresh
+        }
+        Symbol(s) => {
+            renames.remove(s);
+            pattern.clone()
+        }
+        List(list) => List(
+            list.iter()
+                .map(|p| bind(p, from_caller, renames))
+                .collect::<Vec<_>>()
+                .into(),
+        ),
+        _ => pattern.clone(),
+    }
+}
+
+// like `bind`, for a keyword param and its default. one whose name gets
+// renamed is still given with the keyword it was written with, as
+// `((:keyword renamed) default)`
+fn bind_key(
+    name: &LispValue,
+    default: Option<LispValue>,
+    from_caller: &HashSet<u64>,
+    renames: &mut HashMap<SymbolValue, LispValue>,
+) -> LispValue {
+    use LispValue::*;
+
+    let bound = bind(name, from_caller, renames);
+    let bound = match (name, &bound) {
+        (Symbol(s), Symbol(renamed)) if s != renamed => {
+            List(vec![Symbol(params::keyword_for(s)), bound].into())
+        }
+        _ => bound,
+    };
+    match default {
+        Some(default) => List(vec![bound, default].into()),
+        None if bound.is_list() => List(vec![bound].into()),
+        None => bound,
+    }
+}
+
+// the bindings of a `let` written out as a quoted list, and the `quote`
+fn quoted_pairs(bindings: &LispValue) -> Option<(&LispValue, &[LispValue])> {
+    match bindings.get_list()? {
+        [quote, LispValue::List(pairs)] if quote.get_symbol() == Some("quote") => {
+            Some((quote, pairs))
+        }
+        _ => None,
+    }
+}
+
+// the names bound by a `let` whose bindings are written out, quoted or not
+fn let_names(bindings: &LispValue) -> Vec<SymbolValue> {
+    let_pairs(bindings)
+        .unwrap_or_default()
+        .iter()
+        .filter_map(|binding| match binding.get_list() {
+            Some([pattern, _]) => Pattern::parse(pattern).ok(),
+            _ => None,
+        })
+        .flat_map(|pattern| pattern.names())
+        .collect()
+}
+
+fn symbol_ids(val: &LispValue, ids: &mut HashSet<u64>) {
+    match val {
+        LispValue::Symbol(s) => {
+            ids.insert(s.site());
+        }
+        LispValue::List(list) => list.iter().for_each(|v| symbol_ids(v, ids)),
+        _ => (),
+    }
+}
@@ -0,0 +1,557 @@
+// Partial evaluation
+//
+// `reduce` folds away everything that can be known ahead of time and leaves
+// the rest behind as residual code. evaluating the residual program in the
+// same environment, plus whatever free variables it mentions, gives the same
+// result as evaluating the original program would have.
+
+use super::{Bindings, LispEnv};
+use crate::eval::{EvalError, ELSE};
+use crate::params::{let_pairs, sequence, FnForm, ParamList, DEF};
+use crate::value::{LambdaValue, MacroValue, SymbolValue, Value as LispValue};
+
+use std::collections::HashSet;
+use std::sync::Arc;
+
+// lambdas with bodies bigger than this are never inlined
+const INLINE_SIZE: usize = 16;
+
+// how many inlined lambdas may be nested inside each other
+const INLINE_DEPTH: usize = 4;
+
+#[derive(Clone, Default)]
+struct Context {
+    // names that will be bound at runtime, but whose values aren't known yet
+    dynamic: HashSet<SymbolValue>,
+    // names bound to something other than what they mean where the residual
+    // code ends up, so they have to be replaced by their values
+    shadowed: HashSet<SymbolValue>,
+    // whether this code only runs under a condition that isn't known yet, so
+    // lambdas shouldn't be called outright in case they never return
+    speculative: bool,
+    // inside `unsafe`, unsafe functions run instead of being described
+    in_unsafe: bool,
+    // number of inlined lambdas this code is nested inside of
+    depth: usize,
+}
+
+enum Stop {
+    Error(EvalError),
+    // the code can't be reduced and mentions shadowed names, so the nearest
+    // enclosing lambda call or `let` has to be left as it was
+    Opaque,
+}
+
+impl From<EvalError> for Stop {
+    fn from(e: EvalError) -> Stop {
+        Stop::Error(e)
+    }
+}
+
+type Reduced = Result<LispValue, Stop>;
+
+impl LispEnv<'_> {
+    pub fn reduce(&self, val: &LispValue) -> Result<LispValue, EvalError> {
+        match self.reduce_in(val, &Context::default()) {
+            Ok(code) => Ok(code),
+            Err(Stop::Error(e)) => Err(e),
+            Err(Stop::Opaque) => Ok(val.clone()),
+        }
+    }
+
+    fn reduce_in(&self, val: &LispValue, ctx: &Context) -> Reduced {
+        use LispValue::*;
+
+        self.step()?;
+
+        match val {
+            List(list) if !list.is_empty() => self.reduce_call(val, list, ctx),
+            Symbol(s) => Ok(self.reduce_symbol(val, s, ctx)),
+            // everything else evaluates to itself
+            _ => Ok(val.clone()),
+        }
+    }
+
+    fn reduce_all(&self, vals: &[LispValue], ctx: &Context) -> Result<Vec<LispValue>, Stop> {
+        vals.iter().map(|val| self.reduce_in(val, ctx)).collect()
+    }
+
+    fn reduce_symbol(&self, val: &LispValue, s: &SymbolValue, ctx: &Context) -> LispValue {
+        if ctx.dynamic.contains(s) {
+            return val.clone();
+        }
+
+        match self.lookup(s) {
+            // free variables are left for whoever evaluates the residual code
+            None => val.clone(),
+            // keep the names of functions around for readability when they mean
+            // the same thing where the code ends up
+            Some(value) if !ctx.shadowed.contains(s) && is_function(value) => val.clone(),
+            Some(value) => as_code(value.clone()),
+        }
+    }
+
+    // the value a piece of residual code is known to evaluate to, if any
+    fn known(&self, code: &LispValue, ctx: &Context) -> Option<LispValue> {
+        use LispValue::*;
+
+        match code {
+            Symbol(_) if code.is_keyword() => Some(code.clone()),
+            Symbol(s) if !ctx.shadowed.contains(s) && !ctx.dynamic.contains(s) => {
+                self.lookup(s).filter(|value| is_function(value)).cloned()
+            }
+            Symbol(_) => None,
+            List(list) if list.is_empty() => Some(code.clone()),
+            List(list) if is_quote(list) => Some(list[1].clone()),
+            List(_) => None,
+            _ => Some(code.clone()),
+        }
+    }
+
+    // code that can't be reduced is left as is, unless it is going somewhere
+    // its names might mean something else
+    fn opaque(&self, val: &LispValue, ctx: &Context) -> Reduced {
+        if mentions_any(val, &ctx.shadowed) {
+            Err(Stop::Opaque)
+        } else {
+            Ok(val.clone())
+        }
+    }
+
+    // the result of running something ahead of time, or the call itself if it
+    // failed so that the error, if it is ever reached, happens at runtime
+    fn attempt(
+        &self,
+        result: Result<LispValue, EvalError>,
+        fallback: impl FnOnce() -> Reduced,
+    ) -> Reduced {
+        match result {
+            Ok(value) => Ok(as_code(value)),
+            Err(e) if e.is_exhausted() => Err(Stop::Error(e)),
+            Err(_) => fallback(),
+        }
+    }
+
+    fn is_definition(&self, form: &LispValue) -> bool {
+        match form.get_list() {
+            Some([head, ..]) => self.is_builtin(head, DEF),
+            _ => false,
+        }
+    }
+
+    fn special_form(&self, head: &LispValue, ctx: &Context) -> Option<&'static str> {
+        // a builtin put at the head itself, like the `do` of a body
+        if let LispValue::Func(builtin) = head {
+            return Some(builtin.name).filter(|_| builtin.is_lazy());
+        }
+        let name = head.get_symbol_value()?;
+        if ctx.dynamic.contains(&name) {
+            return None;
+        }
+        // built-ins that don't simply evaluate all of their arguments
+        match self.lookup(&name)? {
+            LispValue::Func(builtin) if builtin.is_lazy() => Some(builtin.name),
+            _ => None,
+        }
+    }
+
+    fn reduce_call(&self, val: &LispValue, list: &[LispValue], ctx: &Context) -> Reduced {
+        use LispValue::*;
+
+        let (head, args) = (&list[0], &list[1..]);
+
+        if let Some(name) = self.special_form(head, ctx) {
+            return self.reduce_special(name, val, head, args, ctx);
+        }
+
+        let head_code = self.reduce_in(head, ctx)?;
+
+        let f = match self.known(&head_code, ctx) {
+            Some(f) => f,
+            None => return Ok(call(head_code, self.reduce_all(args, ctx)?)),
+        };
+
+        match &f {
+            // macros see their arguments as written, so only the expansion is reduced
+            Macro(mac) => match self.macro_expand(mac, args) {
+                Ok(expansion) => self.reduce_in(&expansion, ctx),
+                Err(e) if e.is_exhausted() => Err(Stop::Error(e)),
+                Err(_) => self.opaque(val, ctx),
+            },
+            // effects like `include` read their arguments as written, so
+            // they are left alone
+            UnsafeFunc(func) if func.builtin.is_lazy() => self.opaque(val, ctx),
+            Func(_) | Lambda(_) | UnsafeFunc(_) => {
+                let arg_codes = self.reduce_all(args, ctx)?;
+                let known = arg_codes
+                    .iter()
+                    .map(|code| self.known(code, ctx))
+                    .collect::<Option<Vec<_>>>();
+
+                match (&f, known) {
+                    // effects are described rather than run
+                    (UnsafeFunc(_), Some(values)) if !ctx.in_unsafe => {
+                        Ok(LispValue::unsafe_call(&f, values))
+                    }
+                    (Lambda(lambda), _) if ctx.speculative || ctx.in_unsafe => {
+                        self.inline(lambda, head_code, arg_codes, ctx)
+                    }
+                    (Func(_), Some(values)) => {
+                        let quoted = values
+                            .into_iter()
+                            .map(LispValue::quoted)
+                            .collect::<Vec<_>>();
+                        self.attempt(self.apply(&f, &quoted), || Ok(call(head_code, arg_codes)))
+                    }
+                    // the body might still mention free variables, so fall back on
+                    // inlining when calling it outright doesn't work
+                    (Lambda(lambda), Some(values)) => {
+                        let quoted = values
+                            .into_iter()
+                            .map(LispValue::quoted)
+                            .collect::<Vec<_>>();
+                        self.attempt(self.apply(&f, &quoted), || {
+                            self.inline(lambda, head_code, arg_codes, ctx)
+                        })
+                    }
+                    (Lambda(lambda), None) => self.inline(lambda, head_code, arg_codes, ctx),
+                    _ => Ok(call(head_code, arg_codes)),
+                }
+            }
+            Bool(_) | Integer(_) | Symbol(_) | List(_) | UnsafeCall(_) => {
+                Err(Stop::Error(EvalError::String(format!(
+                    "[internal fn: reduce] value cannot be called: {}",
+                    &f
+                ))))
+            }
+        }
+    }
+
+    fn reduce_special(
+        &self,
+        name: &str,
+        val: &LispValue,
+        head: &LispValue,
+        args: &[LispValue],
+        ctx: &Context,
+    ) -> Reduced {
+        let head_code = self.reduce_in(head, ctx)?;
+
+        match (name, args) {
+            ("quote", _) => Ok(val.clone()),
+            ("macro", _) => self.attempt(self.eval(val), || Ok(val.clone())),
+            ("if", [condition, then, otherwise]) => {
+                let condition = self.reduce_in(condition, ctx)?;
+                match self.known(&condition, ctx) {
+                    Some(value) if value.is_truthy() => self.reduce_in(then, ctx),
+                    Some(_) => self.reduce_in(otherwise, ctx),
+                    None => {
+                        let branch_ctx = Context {
+                            speculative: true,
+                            ..ctx.clone()
+                        };
+                        let then = self.reduce_in(then, &branch_ctx)?;
+                        let otherwise = self.reduce_in(otherwise, &branch_ctx)?;
+                        Ok(call(head_code, vec![condition, then, otherwise]))
+                    }
+                }
+            }
+            ("fn", _) => {
+                let form = match FnForm::parse(args) {
+                    Ok(form) => form,
+                    Err(_) => return self.opaque(val, ctx),
+                };
+
+                // a lambda that doesn't need anything unknown can be made right now
+                if !form
+                    .clauses
+                    .iter()
+                    .any(|(_, body)| mentions_any(body, &ctx.dynamic))
+                {
+                    return self.attempt(self.eval(val), || Ok(val.clone()));
+                }
+
+                let own_name = form.name.and_then(LispValue::get_symbol_value);
+                let mut clauses = vec![];
+                for (params, body) in form.clauses.iter() {
+                    let mut names = match ParamList::parse(params) {
+                        Ok(params) => params.names(),
+                        Err(_) => return self.opaque(val, ctx),
+                    };
+                    names.extend(own_name);
+
+                    let mut body_ctx = ctx.clone();
+                    body_ctx.shadowed.retain(|name| !names.contains(name));
+                    body_ctx.dynamic.extend(names);
+                    body_ctx.speculative = true;
+                    clauses.push(((*params).clone(), self.reduce_in(body, &body_ctx)?));
+                }
+                Ok(form.rebuild(head_code, form.name.cloned(), clauses))
+            }
+            ("let", [bindings, body @ ..]) if !body.is_empty() => {
+                let body = &sequence(body);
+                let bindings = match let_pairs(bindings) {
+                    Some(bindings) => bindings,
+                    None => return self.opaque(val, ctx),
+                };
+
+                let mut statics = Bindings::new();
+                let mut residual = vec![];
+                let mut dynamic = vec![];
+                for binding in bindings.iter() {
+                    let (name, expr) = match binding.get_list() {
+                        Some([LispValue::Symbol(name), expr]) => (*name, expr),
+                        _ => return self.opaque(val, ctx),
+                    };
+                    let code = self.reduce_in(expr, ctx)?;
+                    match self.known(&code, ctx) {
+                        Some(value) => {
+                            statics.insert(name, value);
+                        }
+                        None => {
+                            residual.push(call(LispValue::Symbol(name), vec![code]));
+                            dynamic.push(name);
+                        }
+                    }
+                }
+
+                // the known bindings are dropped from the residual code
+                let mut body_ctx = ctx.clone();
+                body_ctx.dynamic.retain(|name| !statics.contains_key(name));
+                body_ctx.shadowed.extend(statics.keys().cloned());
+                body_ctx.shadowed.retain(|name| !dynamic.contains(name));
+                body_ctx.dynamic.extend(dynamic);
+
+                let inner_env = self.new_inner_from_parts(statics, self.unsafe_level);
+                match inner_env.reduce_in(body, &body_ctx) {
+                    Ok(body) if residual.is_empty() => Ok(body),
+                    Ok(body) => Ok(residual_let(residual, body)),
+                    Err(Stop::Opaque) => self.opaque(val, ctx),
+                    Err(stop) => Err(stop),
+                }
+            }
+            // forms whose values are already known have nothing left to do
+            // but be thrown away
+            // definitions are left for runtime, along with the rest of the body
+            ("do", forms) if forms.iter().any(|form| self.is_definition(form)) => {
+                self.opaque(val, ctx)
+            }
+            ("do", forms) => {
+                let mut codes = vec![];
+                for form in forms {
+                    let code = self.reduce_in(form, ctx)?;
+                    if let Some(previous) = codes.pop() {
+                        if self.known(&previous, ctx).is_none() {
+                            codes.push(previous);
+                        }
+                    }
+                    codes.push(code);
+                }
+                match &codes[..] {
+                    [] => Ok(LispValue::nil()),
+                    [code] => Ok(code.clone()),
+                    _ => Ok(call(head_code, codes)),
+                }
+            }
+            ("unsafe", [body]) => {
+                let body_ctx = Context {
+                    in_unsafe: true,
+                    ..ctx.clone()
+                };
+                let body = self.reduce_in(body, &body_ctx)?;
+                match self.known(&body, ctx) {
+                    // still has to be run, but it's known what to run
+                    Some(LispValue::UnsafeCall(_)) | None => Ok(call(head_code, vec![body])),
+                    Some(_) => Ok(body),
+                }
+            }
+            ("eval", [form]) => {
+                let form_code = self.reduce_in(form, ctx)?;
+                match self.known(&form_code, ctx) {
+                    Some(form) => self.reduce_in(&form, ctx),
+                    None if !ctx.shadowed.is_empty() => Err(Stop::Opaque),
+                    None => Ok(call(head_code, vec![form_code])),
+                }
+            }
+            // patterns aren't code, so the whole thing is either run now or
+            // left alone
+            ("match", _) => {
+                let runnable = !ctx.speculative && !ctx.in_unsafe;
+                if runnable && !mentions_any(val, &ctx.dynamic) {
+                    self.attempt(self.eval(val), || self.opaque(val, ctx))
+                } else {
+                    self.opaque(val, ctx)
+                }
+            }
+            // only the first form is sure to run, and none of them bind anything
+            ("and" | "or" | "when" | "unless", [first, rest @ ..]) => {
+                let rest_ctx = Context {
+                    speculative: true,
+                    ..ctx.clone()
+                };
+                let mut codes = vec![self.reduce_in(first, ctx)?];
+                for form in rest {
+                    codes.push(self.reduce_in(form, &rest_ctx)?);
+                }
+                Ok(call(head_code, codes))
+            }
+            ("cond", clauses) => {
+                let clause_ctx = Context {
+                    speculative: true,
+                    ..ctx.clone()
+                };
+                let mut codes = vec![];
+                for clause in clauses {
+                    let forms = match clause.get_list() {
+                        Some(forms) => forms,
+                        None => return self.opaque(val, ctx),
+                    };
+                    let mut clause_code = vec![];
+                    for form in forms {
+                        if clause_code.is_empty() && form.get_symbol() == Some(ELSE) {
+                            clause_code.push(form.clone());
+                        } else {
+                            clause_code.push(self.reduce_in(form, &clause_ctx)?);
+                        }
+                    }
+                    codes.push(LispValue::List(clause_code.into()));
+                }
+                Ok(call(head_code, codes))
+            }
+            // wrong number of arguments, so leave it to fail at runtime
+            _ => self.opaque(val, ctx),
+        }
+    }
+
+    // substitute what is known about the arguments into the body of a lambda,
+    // binding the rest with a `let` around it
+    fn inline(
+        &self,
+        lambda: &LambdaValue,
+        head_code: LispValue,
+        arg_codes: Vec<LispValue>,
+        ctx: &Context,
+    ) -> Reduced {
+        let (params, body) = match lambda.clause(arg_codes.len()) {
+            Ok((params, body)) => (params.plain_names(), body),
+            Err(_) => return Ok(call(head_code, arg_codes)),
+        };
+
+        if ctx.depth >= INLINE_DEPTH || size(body) > INLINE_SIZE {
+            return Ok(call(head_code, arg_codes));
+        }
+
+        // TODO: inline calls to functions with optional, keyword, rest or pattern params
+        let params = match params {
+            Some(names) if names.len() == arg_codes.len() => names,
+            _ => return Ok(call(head_code, arg_codes)),
+        };
+
+        let mut statics = Bindings::new();
+        let mut residual = vec![];
+        let mut dynamic = vec![];
+        for (param, code) in params.iter().zip(arg_codes.iter()) {
+            match self.known(code, ctx) {
+                Some(value) => {
+                    statics.insert(*param, value);
+                }
+                None => {
+                    dynamic.push(*param);
+                    residual.push(call(LispValue::Symbol(*param), vec![code.clone()]));
+                }
+            }
+        }
+
+        // scoped the same way `apply` does it, with the params on top of the
+        // closure. most of a closure is usually the same bindings the caller
+        // can already see
+        let closure = &lambda.scope();
+        let mut body_ctx = ctx.clone();
+        for (name, value) in closure.iter() {
+            body_ctx.dynamic.remove(name);
+            if !self
+                .lookup(name)
+                .is_some_and(|outer| same_binding(outer, value))
+            {
+                body_ctx.shadowed.insert(*name);
+            }
+        }
+        body_ctx.dynamic.retain(|name| !statics.contains_key(name));
+        body_ctx.shadowed.extend(statics.keys().cloned());
+        body_ctx.shadowed.retain(|name| !dynamic.contains(name));
+        body_ctx.dynamic.extend(dynamic);
+        body_ctx.depth += 1;
+
+        let closure_env = self.new_inner_from_parts(closure.clone(), self.unsafe_level);
+        let inner_env = closure_env.new_inner_from_parts(statics, self.unsafe_level);
+
+        match inner_env.reduce_in(body, &body_ctx) {
+            Ok(body) if residual.is_empty() => Ok(body),
+            Ok(body) => Ok(residual_let(residual, body)),
+            Err(Stop::Opaque) => Ok(call(head_code, arg_codes)),
+            Err(stop) => Err(stop),
+        }
+    }
+}
+
+fn is_function(val: &LispValue) -> bool {
+    use LispValue::*;
+    matches!(val, Func(_) | UnsafeFunc(_) | Lambda(_) | Macro(_))
+}
+
+// whether two values are known to be the very same binding
+fn same_binding(a: &LispValue, b: &LispValue) -> bool {
+    use LispValue::*;
+    match (a, b) {
+        (Func(a), Func(b)) => std::ptr::fn_addr_eq(a.func, b.func),
+        (UnsafeFunc(a), UnsafeFunc(b)) => a.builtin.name == b.builtin.name,
+        (Lambda(a), Lambda(b)) => Arc::ptr_eq(&a.clauses, &b.clauses),
+        (
+            Macro(MacroValue::Procedural { body: a, .. }),
+            Macro(MacroValue::Procedural { body: b, .. }),
+        ) => Arc::ptr_eq(a, b),
+        (Macro(MacroValue::Rules(a)), Macro(MacroValue::Rules(b))) => Arc::ptr_eq(a, b),
+        _ => a == b,
+    }
+}
+
+fn is_quote(list: &[LispValue]) -> bool {
+    list.len() == 2 && list[0] == LispValue::Symbol("quote".into())
+}
+
+// code that evaluates to the given value
+fn as_code(val: LispValue) -> LispValue {
+    match val {
+        LispValue::Symbol(_) => LispValue::quoted(val),
+        LispValue::List(ref list) if !list.is_empty() => LispValue::quoted(val),
+        _ => val,
+    }
+}
+
+fn call(head: LispValue, args: Vec<LispValue>) -> LispValue {
+    let mut list = vec![head];
+    list.extend(args);
+    LispValue::List(list.into())
+}
+
+fn residual_let(bindings: Vec<LispValue>, body: LispValue) -> LispValue {
+    call(
+        LispValue::Symbol("let".into()),
+        vec![LispValue::List(bindings.into()), body],
+    )
+}
+
+fn size(val: &LispValue) -> usize {
+    match val {
+        LispValue::List(list) => 1 + list.iter().map(size).sum::<usize>(),
+        _ => 1,
+    }
+}
+
+fn mentions_any(val: &LispValue, names: &HashSet<SymbolValue>) -> bool {
+    match val {
+        LispValue::Symbol(s) => names.contains(s),
+        LispValue::List(list) => list.iter().any(|v| mentions_any(v, names)),
+        _ => false,
+    }
+}
@@ -1,8 +1,17 @@
-use super::env::LispEnv;
+use super::builtin::{Args, Arity, Builtin, Evaluation, FuncValue};
+use super::capability::Capability;
+use super::env::{Bindings, LispEnv};
+use super::mac::{gensym, SyntaxRules};
+use super::matching::MatchPattern;
+use super::params::{let_pairs, sequence, FnForm, ParamList, Pattern, DEF, DO};
 use super::value::LambdaValue;
+use super::value::ListValue;
 use super::value::MacroValue;
+use super::value::SymbolValue;
+use super::value::UnsafeFuncValue;
 use super::value::Value as LispValue;
-use std::collections::HashMap;
+use std::cmp::Ordering;
+use std::collections::{HashMap, HashSet};
 use std::sync::Arc;
 
 mod lua;
@@ -12,11 +21,24 @@ pub enum EvalError {
     Static(&'static str),
     String(String),
     AttemptToClone,
+    OutOfFuel,
+    DeadlineExceeded,
+    OutOfMemory,
+    TooDeep,
+    MissingCapability(Capability, String),
     Boxed(Box<dyn std::error::Error>),
 }
 
 impl std::error::Error for EvalError {}
 
+impl EvalError {
+    // whether this is a budget running out, rather than a problem with the code
+    pub fn is_exhausted(&self) -> bool {
+        use EvalError::*;
+        matches!(self, OutOfFuel | DeadlineExceeded | OutOfMemory | TooDeep)
+    }
+}
+
 impl core::fmt::Display for EvalError {
     fn fmt(&self, f: &mut core::fmt::Formatter) -> core::fmt::Result {
         use EvalError::*;
@@ -24,468 +46,1083 @@ impl core::fmt::Display for EvalError {
             Static(s) => write!(f, "{}", s),
             String(s) => write!(f, "{}", s),
             AttemptToClone => write!(f, "Attempt To Clone"),
+            OutOfFuel => write!(f, "evaluation ran out of fuel"),
+            DeadlineExceeded => write!(f, "evaluation exceeded its deadline"),
+            OutOfMemory => write!(f, "evaluation exceeded its memory limit"),
+            TooDeep => write!(f, "evaluation nested deeper than its limit"),
+            MissingCapability(capability, user) => write!(
+                f,
+                "`{}` requires the {} capability, which has not been granted",
+                user, capability
+            ),
             Boxed(b) => write!(f, "Boxed Error {}", *b),
         }
     }
 }
 
-pub fn eval_err(s: &'static str) -> EvalError {
-    EvalError::Static(s)
-}
-
 impl From<Box<dyn std::error::Error>> for EvalError {
     fn from(other: Box<dyn std::error::Error>) -> EvalError {
         EvalError::Boxed(other)
     }
 }
 
+// run the effect described by an `UnsafeCall`, and then any effect that
+// produces in turn, until a plain value comes out
+//
+// anything other than an `UnsafeCall` is returned as is. an effect left inside
+// a list is an error rather than something to hand back unrun
+pub fn run_effects(env: &LispEnv, val: LispValue) -> Result<LispValue, EvalError> {
+    let inner_env = env.new_unsafer_env();
+
+    let mut val = val;
+    while let LispValue::UnsafeCall(call) = &val {
+        // strict arguments were evaluated when the call was described, and
+        // lazy ones are still the forms as written
+        val = match &call[0] {
+            LispValue::UnsafeFunc(func) if func.builtin.is_lazy() => {
+                inner_env.apply(&call[0], &call[1..])?
+            }
+            _ => inner_env.call(&call[0], &call[1..])?,
+        };
+    }
+
+    match nested_effect(&val) {
+        Some(call) => Err(EvalError::String(format!(
+            "[unsafe] can't run an effect inside a value, got {} in {}",
+            call, val
+        ))),
+        None => Ok(val),
+    }
+}
+
+// the first `UnsafeCall` anywhere inside a list
+fn nested_effect(val: &LispValue) -> Option<&LispValue> {
+    let mut lists = vec![val.get_list_value()?];
+    while let Some(list) = lists.pop() {
+        for item in list.iter() {
+            match item {
+                LispValue::UnsafeCall(_) => return Some(item),
+                LispValue::List(inner) => lists.push(inner),
+                _ => (),
+            }
+        }
+    }
+    None
+}
+
+// the test of a `cond` clause that is always taken
+pub const ELSE: &str = "else";
+
+fn overflow(args: &Args) -> EvalError {
+    args.error("integer overflow")
+}
+
+fn checked_division(
+    args: &Args,
+    a: i64,
+    b: i64,
+    op: fn(i64, i64) -> Option<i64>,
+) -> Result<i64, EvalError> {
+    if b == 0 {
+        return Err(args.error("division by zero"));
+    }
+    op(a, b).ok_or_else(|| overflow(args))
+}
+
+// the order of two values, numbers by value and symbols by name
+fn compare(args: &Args, a: &LispValue, b: &LispValue) -> Result<Ordering, EvalError> {
+    use LispValue::*;
+
+    match (a, b) {
+        (Integer(a), Integer(b)) => Ok(a.cmp(b)),
+        (Symbol(a), Symbol(b)) => Ok(a.cmp(b)),
+        _ => Err(args.error(format!("can't compare {} with {}", a, b))),
+    }
+}
+
+// whether every argument is in `accept`ed order with the one after it
+fn chain(args: &Args, accept: fn(Ordering) -> bool) -> Result<LispValue, EvalError> {
+    for pair in args.windows(2) {
+        if !accept(compare(args, &pair[0], &pair[1])?) {
+            return Ok(LispValue::Bool(false));
+        }
+    }
+    Ok(LispValue::Bool(true))
+}
+
+// combine the values of a list with `f`, from the left
+fn fold(
+    env: &LispEnv,
+    f: &LispValue,
+    init: LispValue,
+    list: &ListValue,
+) -> Result<LispValue, EvalError> {
+    let mut acc = init;
+    for value in list.iter() {
+        acc = env.call(f, &[acc, value.clone()])?;
+    }
+    Ok(acc)
+}
+
+// a stable sort that gives up on the first comparison that fails. a comparison
+// that isn't consistent gives some order rather than a panic
+fn merge_sort(
+    mut values: Vec<LispValue>,
+    less: &mut impl FnMut(&LispValue, &LispValue) -> Result<bool, EvalError>,
+) -> Result<Vec<LispValue>, EvalError> {
+    if values.len() < 2 {
+        return Ok(values);
+    }
+    let right = values.split_off(values.len() / 2);
+    let (left, right) = (merge_sort(values, less)?, merge_sort(right, less)?);
+
+    let mut merged = Vec::with_capacity(left.len() + right.len());
+    let (mut left, mut right) = (left.into_iter().peekable(), right.into_iter().peekable());
+    while let (Some(a), Some(b)) = (left.peek(), right.peek()) {
+        // ties go to the left, which came first
+        if less(b, a)? {
+            merged.extend(right.next());
+        } else {
+            merged.extend(left.next());
+        }
+    }
+    merged.extend(left);
+    merged.extend(right);
+    Ok(merged)
+}
+
+// evaluate forms one after the other for the value of the last, or `()` if
+// there are none. a run of `def`s binds its names for the forms after it, as
+// if by `letrec`
+fn eval_sequence(forms: &[LispValue], env: &LispEnv) -> Result<LispValue, EvalError> {
+    let mut result = LispValue::nil();
+    for (i, form) in forms.iter().enumerate() {
+        if definition(form, env).is_none() {
+            result = env.eval(form)?;
+            continue;
+        }
+        let defs = forms[i..]
+            .iter()
+            .map_while(|form| definition(form, env))
+            .collect::<Vec<_>>();
+        let bindings = defs
+            .iter()
+            .map(|def| match def {
+                [pattern, value] => Ok((Pattern::parse(pattern)?, value.clone())),
+                _ => Err(EvalError::String(format!(
+                    "[{}] expected a name and a value, got {}",
+                    DEF,
+                    LispValue::List(def.to_vec().into())
+                ))),
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+        let (patterns, mut values) = bind_in_sequence(bindings, env)?;
+        bind_recursively(&patterns, &mut values);
+        let rest = &forms[i + defs.len()..];
+        return eval_sequence(rest, &env.new_bound_env(patterns, values)?);
+    }
+    Ok(result)
+}
+
+// the arguments of a `(def name value)` form
+fn definition<'v>(form: &'v LispValue, env: &LispEnv) -> Option<&'v [LispValue]> {
+    match form.get_list()? {
+        [head, args @ ..] if env.is_builtin(head, DEF) => Some(args),
+        _ => None,
+    }
+}
+
+// the `(pattern value)` pairs of a `let` and the like. bindings that are
+// neither written out nor quoted are evaluated for the pairs, as all of them
+// used to be
+fn let_bindings(args: &Args, env: &LispEnv) -> Result<Vec<(Pattern, LispValue)>, EvalError> {
+    let evaluated;
+    let pairs = match let_pairs(&args[0]) {
+        Some(pairs) => pairs,
+        None => {
+            evaluated = env.eval(&args[0])?;
+            let list = evaluated.get_list_value().ok_or_else(|| {
+                args.error(format!("expected a list of bindings, got {}", evaluated))
+            })?;
+            env.slice(list)?
+        }
+    };
+    pairs
+        .iter()
+        .map(|pair| match pair.get_list() {
+            Some([pattern, value]) => Ok((Pattern::parse(pattern)?, value.clone())),
+            _ => Err(args.error(format!(
+                "a binding must look like (name value), got {}",
+                pair
+            ))),
+        })
+        .collect()
+}
+
+// evaluate each value with the names before it already bound
+fn bind_in_sequence(
+    bindings: Vec<(Pattern, LispValue)>,
+    env: &LispEnv,
+) -> Result<(Vec<Pattern>, Vec<LispValue>), EvalError> {
+    let mut bound = Bindings::new();
+    let mut patterns = vec![];
+    let mut values = vec![];
+    for (pattern, form) in bindings {
+        let value = env.new_matched_env(bound.clone())?.eval(&form)?;
+        pattern.bind(value.clone(), &mut bound)?;
+        patterns.push(pattern);
+        values.push(value);
+    }
+    Ok((patterns, values))
+}
+
+// lambdas bound together call each other, rather than whatever those names
+// meant where the lambdas were made
+fn bind_recursively(patterns: &[Pattern], values: &mut [LispValue]) {
+    let names = patterns
+        .iter()
+        .flat_map(Pattern::names)
+        .collect::<HashSet<_>>();
+    for value in values.iter_mut() {
+        if let LispValue::Lambda(lambda) = value {
+            lambda.closure.retain(|name, _| !names.contains(name));
+        }
+    }
+    let group = patterns
+        .iter()
+        .zip(values.iter())
+        .filter_map(|(pattern, value)| Some((pattern.get_name()?, value.clone())))
+        .collect::<Bindings>();
+    let group = Arc::new(group);
+    for value in values.iter_mut() {
+        if let LispValue::Lambda(lambda) = value {
+            lambda.group = group.clone();
+        }
+    }
+}
+
+// the `do` builtin itself, which a body of several forms is run with. it goes
+// at the head of the body rather than the name, which the body could rebind
+pub const DO_BUILTIN: Builtin = Builtin {
+    name: DO,
+    arity: Arity::AtLeast(0),
+    evaluation: Evaluation::Lazy,
+    func: |args: &Args, env: &LispEnv| -> Result<LispValue, EvalError> { eval_sequence(args, env) },
+};
+
 pub fn default_env<'a>() -> LispEnv<'a> {
     use LispValue::*;
 
-    fn func(
-        s: &'static str,
-        f: fn(&[LispValue], &LispEnv) -> Result<LispValue, EvalError>,
-    ) -> (String, LispValue) {
-        (s.into(), Func(f))
+    // a builtin that gets its arguments already evaluated
+    fn strict(name: &'static str, arity: Arity, func: FuncValue) -> (SymbolValue, LispValue) {
+        let builtin = Builtin {
+            name,
+            arity,
+            evaluation: Evaluation::Strict,
+            func,
+        };
+        (name.into(), Func(builtin))
+    }
+
+    // a builtin that gets its argument forms as written
+    fn lazy(name: &'static str, arity: Arity, func: FuncValue) -> (SymbolValue, LispValue) {
+        let builtin = Builtin {
+            name,
+            arity,
+            evaluation: Evaluation::Lazy,
+            func,
+        };
+        (name.into(), Func(builtin))
     }
 
     fn unsafe_func(
-        s: &'static str,
-        f: fn(&[LispValue], &LispEnv) -> Result<LispValue, EvalError>,
-    ) -> (String, LispValue) {
-        (s.into(), UnsafeFunc(f))
+        name: &'static str,
+        capabilities: &'static [Capability],
+        arity: Arity,
+        evaluation: Evaluation,
+        func: FuncValue,
+    ) -> (SymbolValue, LispValue) {
+        let builtin = Builtin {
+            name,
+            arity,
+            evaluation,
+            func,
+        };
+        (
+            name.into(),
+            UnsafeFunc(UnsafeFuncValue {
+                builtin,
+                capabilities,
+            }),
+        )
     }
 
-    let bindings = HashMap::from([
-        ("exit".into(), Symbol("exit".to_string())),
+    let mut bindings = HashMap::from([
+        ("exit".into(), Symbol("exit".into())),
         ("false".into(), Bool(false)),
         ("true".into(), Bool(true)),
-        func(
+        strict(
             "cons",
-            |args: &[LispValue], env: &LispEnv| -> Result<LispValue, EvalError> {
-                if args.len() != 2 {
-                    return Err(eval_err("[cons] Wrong number of arguments"));
-                }
-                LispValue::cons(&env.eval(&args[0])?, &env.eval(&args[1])?)
-                    .ok_or(eval_err("[cons] tail not a list"))
+            Arity::Exactly(2),
+            |args: &Args, env: &LispEnv| -> Result<LispValue, EvalError> {
+                let tail = args.list(1)?;
+                env.charge_cell()?;
+                Ok(List(tail.cons(args[0].clone())))
             },
         ),
-        func(
+        strict(
             "car",
-            |args: &[LispValue], env: &LispEnv| -> Result<LispValue, EvalError> {
-                if args.len() != 1 {
-                    return Err(eval_err("[car] Wrong number of arguments"));
-                }
-                env.eval(&args[0])?
-                    .head()
-                    .ok_or(eval_err("[car] Wrong argument type"))
+            Arity::Exactly(1),
+            |args: &Args, _env: &LispEnv| -> Result<LispValue, EvalError> {
+                let list = args.list(0)?;
+                Ok(list.first().cloned().unwrap_or_else(LispValue::nil))
             },
         ),
-        func(
+        strict(
             "cdr",
-            |args: &[LispValue], env: &LispEnv| -> Result<LispValue, EvalError> {
-                if args.len() != 1 {
-                    return Err(eval_err("[cdr] Wrong number of arguments"));
-                }
-                env.eval(&args[0])?
-                    .tail()
-                    .ok_or(eval_err("[car] Wrong argument type"))
+            Arity::Exactly(1),
+            |args: &Args, _env: &LispEnv| -> Result<LispValue, EvalError> {
+                // shares the rest of the list, so there's nothing new to charge for
+                Ok(List(args.list(0)?.rest()))
             },
         ),
-        func(
+        lazy(
             "quote",
-            |args: &[LispValue], _env: &LispEnv| -> Result<LispValue, EvalError> {
-                if args.len() != 1 {
-                    return Err(eval_err("[quote] Wrong number of arguments"));
-                }
-                Ok(args[0].clone())
-            },
+            Arity::Exactly(1),
+            |args: &Args, _env: &LispEnv| -> Result<LispValue, EvalError> { Ok(args[0].clone()) },
         ),
-        func(
+        strict(
             "eq",
-            |args: &[LispValue], env: &LispEnv| -> Result<LispValue, EvalError> {
-                if args.len() != 2 {
-                    return Err(eval_err("[eq] Wrong number of arguments"));
-                }
-                let a = env.eval(&args[0])?;
-                let b = env.eval(&args[1])?;
-                Ok(Bool(a == b))
+            Arity::Exactly(2),
+            |args: &Args, _env: &LispEnv| -> Result<LispValue, EvalError> {
+                Ok(Bool(args[0] == args[1]))
             },
         ),
-        func(
+        lazy(
             "macro",
-            |args: &[LispValue], _env: &LispEnv| -> Result<LispValue, EvalError> {
-                if args.len() != 2 {
-                    return Err(eval_err("[macro] Wrong number of arguments"));
-                }
-                if !args[0].is_list_of_symbols() {
-                    return Err(eval_err("[macro] Wrong argument type"));
-                }
-                let a = args[0].get_list().unwrap().to_owned();
-                let b = args[1].clone();
-                Ok(Macro(MacroValue::new(a.into(), Arc::new(b))))
+            Arity::AtLeast(2),
+            |args: &Args, _env: &LispEnv| -> Result<LispValue, EvalError> {
+                ParamList::parse(&args[0])?;
+                let params = args.list(0)?.clone();
+                let body = sequence(&args[1..]);
+                Ok(Macro(MacroValue::new(params, Arc::new(body))))
+            },
+        ),
+        lazy(
+            "syntax-rules",
+            Arity::AtLeast(1),
+            |args: &Args, _env: &LispEnv| -> Result<LispValue, EvalError> {
+                let rules = SyntaxRules::new(&args[0], &args[1..])?;
+                Ok(Macro(MacroValue::Rules(Arc::new(rules))))
             },
         ),
-        func(
+        lazy(
             "fn",
-            |args: &[LispValue], env: &LispEnv| -> Result<LispValue, EvalError> {
-                if args.len() != 2 {
-                    return Err(eval_err("[fn] Wrong number of arguments"));
-                }
-                if !args[0].is_list_of_symbols() {
-                    return Err(eval_err("[fn] Wrong argument type"));
+            Arity::AtLeast(2),
+            |args: &Args, env: &LispEnv| -> Result<LispValue, EvalError> {
+                let form = FnForm::parse(args)?;
+                form.params()?;
+                let mut clauses = Vec::with_capacity(form.clauses.len());
+                for (params, body) in form.clauses {
+                    clauses.push((params.clone(), body));
                 }
-                let a = args[0].clone();
-                let b = args[1].clone();
-                Ok(Lambda(LambdaValue::new(
-                    Arc::new(a),
-                    Arc::new(b),
-                    env.flatten(),
-                )))
+                let name = form.name.and_then(LispValue::get_symbol_value);
+                let closure = env.flatten();
+                env.charge_bindings(&closure)?;
+                Ok(Lambda(LambdaValue::new(name, clauses.into(), closure)))
             },
         ),
-        func(
+        lazy(
             "if",
-            |args: &[LispValue], env: &LispEnv| -> Result<LispValue, EvalError> {
-                if args.len() != 3 {
-                    return Err(eval_err("[if] Wrong number of arguments"));
-                }
-                let condition = env
-                    .eval(&args[0])?
-                    .get_bool()
-                    .ok_or(eval_err("[if] Wrong argument type"))?;
-                let result = if condition {
+            Arity::Exactly(3),
+            |args: &Args, env: &LispEnv| -> Result<LispValue, EvalError> {
+                if env.eval(&args[0])?.is_truthy() {
                     env.eval(&args[1])
                 } else {
                     env.eval(&args[2])
-                };
-                Ok(result?)
+                }
             },
         ),
-        func(
-            "atom",
-            |args: &[LispValue], env: &LispEnv| -> Result<LispValue, EvalError> {
-                if args.len() != 1 {
-                    return Err(eval_err("[atom] Wrong number of arguments"));
+        (DO.into(), Func(DO_BUILTIN)),
+        strict(
+            "not",
+            Arity::Exactly(1),
+            |args: &Args, _env: &LispEnv| -> Result<LispValue, EvalError> {
+                Ok(Bool(!args[0].is_truthy()))
+            },
+        ),
+        lazy(
+            "and",
+            Arity::AtLeast(0),
+            |args: &Args, env: &LispEnv| -> Result<LispValue, EvalError> {
+                // the first false value, or else the last value
+                let mut result = Bool(true);
+                for arg in args.iter() {
+                    result = env.eval(arg)?;
+                    if !result.is_truthy() {
+                        break;
+                    }
                 }
-                let a = env.eval(&args[0])?;
-                Ok(Bool(a.is_atom()))
+                Ok(result)
             },
         ),
-        func(
-            "lt",
-            |args: &[LispValue], env: &LispEnv| -> Result<LispValue, EvalError> {
-                if args.len() != 2 {
-                    return Err(eval_err("[lt] Wrong number of arguments"));
+        lazy(
+            "or",
+            Arity::AtLeast(0),
+            |args: &Args, env: &LispEnv| -> Result<LispValue, EvalError> {
+                // the first true value, or else the last value
+                let mut result = Bool(false);
+                for arg in args.iter() {
+                    result = env.eval(arg)?;
+                    if result.is_truthy() {
+                        break;
+                    }
                 }
-                let a = env
-                    .eval(&args[0])?
-                    .get_int()
-                    .ok_or(eval_err("[lt] Wrong argument type"))?;
-                let b = env
-                    .eval(&args[1])?
-                    .get_int()
-                    .ok_or(eval_err("[lt] Wrong argument type"))?;
-                Ok(Bool(a < b))
-            },
-        ),
-        func(
-            "gt",
-            |args: &[LispValue], env: &LispEnv| -> Result<LispValue, EvalError> {
-                if args.len() != 2 {
-                    return Err(eval_err("[lt] Wrong number of arguments"));
+                Ok(result)
+            },
+        ),
+        lazy(
+            "when",
+            Arity::AtLeast(1),
+            |args: &Args, env: &LispEnv| -> Result<LispValue, EvalError> {
+                if env.eval(&args[0])?.is_truthy() {
+                    eval_sequence(&args[1..], env)
+                } else {
+                    Ok(LispValue::nil())
+                }
+            },
+        ),
+        lazy(
+            "unless",
+            Arity::AtLeast(1),
+            |args: &Args, env: &LispEnv| -> Result<LispValue, EvalError> {
+                if env.eval(&args[0])?.is_truthy() {
+                    Ok(LispValue::nil())
+                } else {
+                    eval_sequence(&args[1..], env)
+                }
+            },
+        ),
+        lazy(
+            "cond",
+            Arity::AtLeast(0),
+            |args: &Args, env: &LispEnv| -> Result<LispValue, EvalError> {
+                // the results of the first clause whose test is true, or the
+                // test itself if the clause has no results
+                for clause in args.iter() {
+                    let (test, results) = match clause.get_list() {
+                        Some([test, results @ ..]) => (test, results),
+                        _ => {
+                            return Err(args.error(format!(
+                                "a clause must look like (test result ...): {}",
+                                clause
+                            )))
+                        }
+                    };
+                    let value = match test.get_symbol() {
+                        Some(ELSE) => Bool(true),
+                        _ => env.eval(test)?,
+                    };
+                    if value.is_truthy() {
+                        return match results {
+                            [] => Ok(value),
+                            _ => eval_sequence(results, env),
+                        };
+                    }
                 }
-                let a = env
-                    .eval(&args[0])?
-                    .get_int()
-                    .ok_or(eval_err("[lt] Wrong argument type"))?;
-                let b = env
-                    .eval(&args[1])?
-                    .get_int()
-                    .ok_or(eval_err("[lt] Wrong argument type"))?;
-                Ok(Bool(a > b))
-            },
-        ),
-        func(
+                Ok(LispValue::nil())
+            },
+        ),
+        strict(
+            "atom",
+            Arity::Exactly(1),
+            |args: &Args, _env: &LispEnv| -> Result<LispValue, EvalError> {
+                Ok(Bool(args[0].is_atom()))
+            },
+        ),
+        strict(
+            "<",
+            Arity::AtLeast(1),
+            |args: &Args, _env: &LispEnv| -> Result<LispValue, EvalError> {
+                chain(args, Ordering::is_lt)
+            },
+        ),
+        strict(
+            "<=",
+            Arity::AtLeast(1),
+            |args: &Args, _env: &LispEnv| -> Result<LispValue, EvalError> {
+                chain(args, Ordering::is_le)
+            },
+        ),
+        strict(
+            ">",
+            Arity::AtLeast(1),
+            |args: &Args, _env: &LispEnv| -> Result<LispValue, EvalError> {
+                chain(args, Ordering::is_gt)
+            },
+        ),
+        strict(
+            ">=",
+            Arity::AtLeast(1),
+            |args: &Args, _env: &LispEnv| -> Result<LispValue, EvalError> {
+                chain(args, Ordering::is_ge)
+            },
+        ),
+        strict(
+            "=",
+            Arity::AtLeast(1),
+            |args: &Args, _env: &LispEnv| -> Result<LispValue, EvalError> {
+                Ok(Bool(args.windows(2).all(|pair| pair[0] == pair[1])))
+            },
+        ),
+        strict(
+            "!=",
+            Arity::AtLeast(1),
+            |args: &Args, _env: &LispEnv| -> Result<LispValue, EvalError> {
+                // only neighbours are compared, like the other chains
+                Ok(Bool(args.windows(2).all(|pair| pair[0] != pair[1])))
+            },
+        ),
+        strict(
             "add",
-            |args: &[LispValue], env: &LispEnv| -> Result<LispValue, EvalError> {
-                // TODO: fix this to have proper error propogation instead of panicking
-                let sum = args
-                    .iter()
-                    .fold(0, |sum, a| sum + env.eval(a).unwrap().get_int().unwrap());
-
-                //.ok_or(eval_err("[add] Wrong argument type"))?
-
-                Ok(Integer(sum))
+            Arity::AtLeast(0),
+            |args: &Args, _env: &LispEnv| -> Result<LispValue, EvalError> {
+                let sum = args.ints()?.into_iter().try_fold(0, i64::checked_add);
+                Ok(Integer(sum.ok_or_else(|| overflow(args))?))
             },
         ),
-        func(
+        strict(
             "sub",
-            |args: &[LispValue], env: &LispEnv| -> Result<LispValue, EvalError> {
-                if args.len() != 2 {
-                    return Err(eval_err("[sub] Wrong number of arguments"));
+            Arity::Exactly(2),
+            |args: &Args, _env: &LispEnv| -> Result<LispValue, EvalError> {
+                let difference = args.int(0)?.checked_sub(args.int(1)?);
+                Ok(Integer(difference.ok_or_else(|| overflow(args))?))
+            },
+        ),
+        strict(
+            "mul",
+            Arity::AtLeast(0),
+            |args: &Args, _env: &LispEnv| -> Result<LispValue, EvalError> {
+                let product = args.ints()?.into_iter().try_fold(1, i64::checked_mul);
+                Ok(Integer(product.ok_or_else(|| overflow(args))?))
+            },
+        ),
+        strict(
+            "div",
+            Arity::AtLeast(2),
+            |args: &Args, _env: &LispEnv| -> Result<LispValue, EvalError> {
+                // each divisor in turn, rounding towards zero
+                let ints = args.ints()?;
+                let mut quotient = ints[0];
+                for &divisor in &ints[1..] {
+                    quotient = checked_division(args, quotient, divisor, i64::checked_div)?;
+                }
+                Ok(Integer(quotient))
+            },
+        ),
+        strict(
+            "rem",
+            Arity::Exactly(2),
+            |args: &Args, _env: &LispEnv| -> Result<LispValue, EvalError> {
+                // takes the sign of the dividend, to go with `div`
+                let rem = checked_division(args, args.int(0)?, args.int(1)?, i64::checked_rem)?;
+                Ok(Integer(rem))
+            },
+        ),
+        strict(
+            "mod",
+            Arity::Exactly(2),
+            |args: &Args, _env: &LispEnv| -> Result<LispValue, EvalError> {
+                // takes the sign of the divisor
+                let (a, b) = (args.int(0)?, args.int(1)?);
+                let rem = checked_division(args, a, b, i64::checked_rem)?;
+                Ok(Integer(if rem != 0 && (rem < 0) != (b < 0) {
+                    rem + b
+                } else {
+                    rem
+                }))
+            },
+        ),
+        strict(
+            "neg",
+            Arity::Exactly(1),
+            |args: &Args, _env: &LispEnv| -> Result<LispValue, EvalError> {
+                let negated = args.int(0)?.checked_neg();
+                Ok(Integer(negated.ok_or_else(|| overflow(args))?))
+            },
+        ),
+        strict(
+            "abs",
+            Arity::Exactly(1),
+            |args: &Args, _env: &LispEnv| -> Result<LispValue, EvalError> {
+                let abs = args.int(0)?.checked_abs();
+                Ok(Integer(abs.ok_or_else(|| overflow(args))?))
+            },
+        ),
+        strict(
+            "min",
+            Arity::AtLeast(1),
+            |args: &Args, _env: &LispEnv| -> Result<LispValue, EvalError> {
+                let ints = args.ints()?;
+                Ok(Integer(ints.into_iter().min().unwrap_or_default()))
+            },
+        ),
+        strict(
+            "max",
+            Arity::AtLeast(1),
+            |args: &Args, _env: &LispEnv| -> Result<LispValue, EvalError> {
+                let ints = args.ints()?;
+                Ok(Integer(ints.into_iter().max().unwrap_or_default()))
+            },
+        ),
+        strict(
+            "pow",
+            Arity::Exactly(2),
+            |args: &Args, _env: &LispEnv| -> Result<LispValue, EvalError> {
+                let (base, exp) = (args.int(0)?, args.int(1)?);
+                if exp < 0 {
+                    return Err(args.error("negative exponent"));
                 }
-                let a = env
-                    .eval(&args[0])?
-                    .get_int()
-                    .ok_or(eval_err("[sub] Wrong argument type"))?;
-                let b = env
-                    .eval(&args[1])?
-                    .get_int()
-                    .ok_or(eval_err("[sub] Wrong argument type"))?;
-                Ok(Integer(a - b))
-            },
-        ),
-        func(
+                let result = u32::try_from(exp)
+                    .ok()
+                    .and_then(|exp| base.checked_pow(exp));
+                Ok(Integer(result.ok_or_else(|| overflow(args))?))
+            },
+        ),
+        strict(
             "list",
-            |args: &[LispValue], env: &LispEnv| -> Result<LispValue, EvalError> {
-                let mut v = Vec::with_capacity(args.len());
-                for arg in args {
-                    v.push(env.eval(arg)?);
+            Arity::AtLeast(0),
+            |args: &Args, env: &LispEnv| -> Result<LispValue, EvalError> {
+                env.charge_list(args.len())?;
+                Ok(List(args.to_vec().into()))
+            },
+        ),
+        strict(
+            "length",
+            Arity::Exactly(1),
+            |args: &Args, _env: &LispEnv| -> Result<LispValue, EvalError> {
+                Ok(Integer(args.list(0)?.len() as i64))
+            },
+        ),
+        strict(
+            "nth",
+            Arity::Exactly(2),
+            |args: &Args, _env: &LispEnv| -> Result<LispValue, EvalError> {
+                let (list, n) = (args.list(0)?, args.index(1)?);
+                list.nth(n).cloned().ok_or_else(|| {
+                    args.error(format!("no element {} in a list of {}", n, list.len()))
+                })
+            },
+        ),
+        strict(
+            "take",
+            Arity::Exactly(2),
+            |args: &Args, env: &LispEnv| -> Result<LispValue, EvalError> {
+                let list = args.list(1)?;
+                let n = args.index(0)?.min(list.len());
+                env.charge_list(n)?;
+                Ok(List(list.iter().take(n).cloned().collect()))
+            },
+        ),
+        strict(
+            "drop",
+            Arity::Exactly(2),
+            |args: &Args, _env: &LispEnv| -> Result<LispValue, EvalError> {
+                // shares the rest of the list, like `cdr`
+                Ok(List(args.list(1)?.skip(args.index(0)?)))
+            },
+        ),
+        strict(
+            "reverse",
+            Arity::Exactly(1),
+            |args: &Args, env: &LispEnv| -> Result<LispValue, EvalError> {
+                let list = args.list(0)?;
+                env.charge_list(list.len())?;
+                let reversed = list.iter().fold(ListValue::default(), |reversed, value| {
+                    reversed.cons(value.clone())
+                });
+                Ok(List(reversed))
+            },
+        ),
+        strict(
+            "append",
+            Arity::AtLeast(0),
+            |args: &Args, env: &LispEnv| -> Result<LispValue, EvalError> {
+                let mut appended = vec![];
+                for i in 0..args.len() {
+                    appended.extend(args.list(i)?.iter().cloned());
                 }
-                Ok(List(v.into()))
+                env.charge_list(appended.len())?;
+                Ok(List(appended.into()))
             },
         ),
-        func(
-            "let",
-            |args: &[LispValue], env: &LispEnv| -> Result<LispValue, EvalError> {
-                if args.len() != 2 {
-                    return Err(eval_err("[let] Wrong number of arguments"));
+        strict(
+            "range",
+            Arity::Between(1, 3),
+            |args: &Args, env: &LispEnv| -> Result<LispValue, EvalError> {
+                // `(range end)`, `(range start end)` or `(range start end step)`
+                let (start, end, step) = match args.len() {
+                    1 => (0, args.int(0)?, 1),
+                    2 => (args.int(0)?, args.int(1)?, 1),
+                    _ => (args.int(0)?, args.int(1)?, args.int(2)?),
+                };
+                if step == 0 {
+                    return Err(args.error("the step can't be 0"));
                 }
-
-                let bindings_list = env.eval(&args[0])?;
-
-                let mut names = vec![];
+                // the number of steps from `start` to `end`, rounded up
+                let (span, step_size) = (i128::from(end) - i128::from(start), i128::from(step));
+                let count = (span + step_size - step_size.signum()) / step_size;
+                env.charge_list(usize::try_from(count).unwrap_or(0))?;
+                let mut range = vec![];
+                let mut n = start;
+                while (step > 0 && n < end) || (step < 0 && n > end) {
+                    range.push(Integer(n));
+                    n = match n.checked_add(step) {
+                        Some(n) => n,
+                        None => break,
+                    };
+                }
+                Ok(List(range.into()))
+            },
+        ),
+        strict(
+            "map",
+            Arity::AtLeast(2),
+            |args: &Args, env: &LispEnv| -> Result<LispValue, EvalError> {
+                // with several lists, `f` gets an element of each until one runs out
+                let lists = (1..args.len())
+                    .map(|i| args.list(i))
+                    .collect::<Result<Vec<_>, _>>()?;
+                let count = lists.iter().map(|list| list.len()).min().unwrap_or(0);
+                env.charge_list(count)?;
+                let mut iters = lists.iter().map(|list| list.iter()).collect::<Vec<_>>();
+                let mut mapped = Vec::with_capacity(count);
+                for _ in 0..count {
+                    let values = iters
+                        .iter_mut()
+                        .filter_map(|iter| iter.next().cloned())
+                        .collect::<Vec<_>>();
+                    mapped.push(env.call(&args[0], &values)?);
+                }
+                Ok(List(mapped.into()))
+            },
+        ),
+        strict(
+            "filter",
+            Arity::Exactly(2),
+            |args: &Args, env: &LispEnv| -> Result<LispValue, EvalError> {
+                let mut kept = vec![];
+                for value in args.list(1)? {
+                    if env.call(&args[0], std::slice::from_ref(value))?.is_truthy() {
+                        kept.push(value.clone());
+                    }
+                }
+                env.charge_list(kept.len())?;
+                Ok(List(kept.into()))
+            },
+        ),
+        strict(
+            "fold",
+            Arity::Exactly(3),
+            |args: &Args, env: &LispEnv| -> Result<LispValue, EvalError> {
+                // from the left, as `(f (f init a) b)`
+                fold(env, &args[0], args[1].clone(), args.list(2)?)
+            },
+        ),
+        strict(
+            "reduce",
+            Arity::Between(2, 3),
+            |args: &Args, env: &LispEnv| -> Result<LispValue, EvalError> {
+                // `fold` with the first element as the initial value, unless
+                // one is given
+                if args.len() == 3 {
+                    return fold(env, &args[0], args[1].clone(), args.list(2)?);
+                }
+                let list = args.list(1)?;
+                match list.first() {
+                    Some(first) => fold(env, &args[0], first.clone(), &list.rest()),
+                    None => Err(args.error("can't reduce an empty list without an initial value")),
+                }
+            },
+        ),
+        strict(
+            "zip",
+            Arity::AtLeast(1),
+            |args: &Args, env: &LispEnv| -> Result<LispValue, EvalError> {
+                let lists = (0..args.len())
+                    .map(|i| args.list(i))
+                    .collect::<Result<Vec<_>, _>>()?;
+                let count = lists.iter().map(|list| list.len()).min().unwrap_or(0);
+                env.charge_list(count.saturating_mul(lists.len() + 1))?;
+                let mut iters = lists.iter().map(|list| list.iter()).collect::<Vec<_>>();
+                let zipped = (0..count)
+                    .map(|_| {
+                        List(
+                            iters
+                                .iter_mut()
+                                .filter_map(|iter| iter.next().cloned())
+                                .collect(),
+                        )
+                    })
+                    .collect();
+                Ok(List(zipped))
+            },
+        ),
+        strict(
+            "sort",
+            Arity::Between(1, 2),
+            |args: &Args, env: &LispEnv| -> Result<LispValue, EvalError> {
+                // `(sort list)` in the order of `<`, or `(sort less list)` where
+                // `(less a b)` is true when `a` goes before `b`
+                let list = args.list(args.len() - 1)?;
+                env.charge_list(list.len())?;
+                let sorted = if args.len() == 1 {
+                    merge_sort(list.iter().cloned().collect(), &mut |a, b| {
+                        Ok(compare(args, a, b)?.is_lt())
+                    })?
+                } else {
+                    merge_sort(list.iter().cloned().collect(), &mut |a, b| {
+                        Ok(env.call(&args[0], &[a.clone(), b.clone()])?.is_truthy())
+                    })?
+                };
+                Ok(List(sorted.into()))
+            },
+        ),
+        strict(
+            "assoc",
+            Arity::Exactly(2),
+            |args: &Args, _env: &LispEnv| -> Result<LispValue, EvalError> {
+                // the first `(key value ...)` entry with the key, or `()`
+                let entry = args.list(1)?.iter().find(|entry| {
+                    entry.get_list_value().and_then(ListValue::first) == Some(&args[0])
+                });
+                Ok(entry.cloned().unwrap_or_else(LispValue::nil))
+            },
+        ),
+        lazy(
+            "let",
+            Arity::AtLeast(2),
+            |args: &Args, env: &LispEnv| -> Result<LispValue, EvalError> {
+                // every value is evaluated outside of the `let`
+                let mut patterns = vec![];
                 let mut values = vec![];
+                for (pattern, form) in let_bindings(args, env)? {
+                    patterns.push(pattern);
+                    values.push(env.eval(&form)?);
+                }
+                eval_sequence(&args[1..], &env.new_bound_env(patterns, values)?)
+            },
+        ),
+        lazy(
+            "let*",
+            Arity::AtLeast(2),
+            |args: &Args, env: &LispEnv| -> Result<LispValue, EvalError> {
+                let (patterns, values) = bind_in_sequence(let_bindings(args, env)?, env)?;
+                eval_sequence(&args[1..], &env.new_bound_env(patterns, values)?)
+            },
+        ),
+        lazy(
+            "letrec",
+            Arity::AtLeast(2),
+            |args: &Args, env: &LispEnv| -> Result<LispValue, EvalError> {
+                let (patterns, mut values) = bind_in_sequence(let_bindings(args, env)?, env)?;
+                bind_recursively(&patterns, &mut values);
+                eval_sequence(&args[1..], &env.new_bound_env(patterns, values)?)
+            },
+        ),
+        lazy(
+            DEF,
+            Arity::Exactly(2),
+            |args: &Args, _env: &LispEnv| -> Result<LispValue, EvalError> {
+                // bodies look for these themselves, so one that gets here isn't in one
+                Err(args.error("can only be used among the forms of a body"))
+            },
+        ),
+        lazy(
+            "match",
+            Arity::AtLeast(1),
+            |args: &Args, env: &LispEnv| -> Result<LispValue, EvalError> {
+                let clauses = args[1..]
+                    .iter()
+                    .map(|clause| match clause.get_list() {
+                        Some([pattern, body]) => Ok((pattern, MatchPattern::parse(pattern)?, body)),
+                        _ => Err(args.error(format!(
+                            "a clause must look like (pattern result): {}",
+                            clause
+                        ))),
+                    })
+                    .collect::<Result<Vec<_>, _>>()?;
 
-                //eprintln!("{}", &bindings_list);
-
-                match &bindings_list {
-                    LispValue::List(l) => {
-                        for b in l.iter() {
-                            match b {
-                                LispValue::List(binding) => {
-                                    if binding.len() != 2 {
-                                        return Err(eval_err("[let] Wrong argument format"));
-                                    }
-                                    names.push(binding[0].clone());
-                                    values.push(env.eval(&binding[1])?);
-                                }
-                                _ => {
-                                    return Err(eval_err(
-                                        "[let] Wrong argument type (not list of list)",
-                                    ))
-                                }
-                            }
-                        }
-                    }
-                    _ => {
-                        return Err(EvalError::String(format!(
-                            "[let] Wrong argument type: [[{}]]",
-                            bindings_list
-                        )))
+                let value = env.eval(&args[0])?;
+                for (_, pattern, body) in clauses.iter() {
+                    let mut bindings = HashMap::new();
+                    if pattern.matches(&value, env, &mut bindings)? {
+                        return env.new_matched_env(bindings)?.eval(body);
                     }
                 }
 
-                env.new_inner_env(Arc::new(LispValue::List(names.into())), &values)?
-                    .eval(&args[1])
+                let tried = clauses
+                    .iter()
+                    .map(|(source, _, _)| source.to_string())
+                    .collect::<Vec<_>>();
+                Err(args.error(format!(
+                    "no pattern matches {}, tried: {}",
+                    value,
+                    tried.join(" ")
+                )))
             },
         ),
-        func(
+        lazy(
             "eval",
-            |args: &[LispValue], env: &LispEnv| -> Result<LispValue, EvalError> {
-                if args.len() != 1 {
-                    return Err(eval_err("[eval] Wrong number of arguments"));
-                }
+            Arity::Exactly(1),
+            |args: &Args, env: &LispEnv| -> Result<LispValue, EvalError> {
                 env.eval(&env.eval(&args[0])?)
             },
         ),
-        func(
-            "unsafe",
-            |args: &[LispValue], env: &LispEnv| -> Result<LispValue, EvalError> {
-                if args.len() != 1 {
-                    return Err(eval_err("[unsafe] Wrong number of arguments"));
+        strict(
+            "apply",
+            Arity::Exactly(2),
+            |args: &Args, env: &LispEnv| -> Result<LispValue, EvalError> {
+                let f = &args[0];
+                let list = env.slice(args.list(1)?)?;
+                match f {
+                    // a macro gets the elements as its argument forms
+                    Macro(_) => env.apply(f, list),
+                    _ => env.call(f, list),
                 }
-
+            },
+        ),
+        lazy(
+            "unsafe",
+            Arity::Exactly(1),
+            |args: &Args, env: &LispEnv| -> Result<LispValue, EvalError> {
                 let inner_env = env.new_unsafer_env();
 
-                Ok(inner_env.eval(&args[0])?)
+                let val = inner_env.eval(&args[0])?;
 
-                /*
-                let val = env.eval(&args[0])?;
-                if let UnsafeCall(list) = val {
-                    if list.len() == 0 {
-                        return Ok(().into());
-                    } else {
-                        let f = env.eval(&list[0])?;
-                        Ok(env.apply(&f, &list[1..])?)
-                    }
-                } else {
-                    Ok(val)
-                }
-                */
-                /*
-                if let Some(list) = val.get_list() {
-                    if list.len() == 0 {
-                        return Ok(().into());
-                    } else {
-                        let f = env.eval(&list[0])?;
-                        Ok(env.apply(&f, &list[1..])?)
-                    }
-                } else {
-                    Ok(val)
-                }
-                */
+                run_effects(env, val)
             },
         ),
         unsafe_func(
             "spookyAdd",
-            |args: &[LispValue], env: &LispEnv| -> Result<LispValue, EvalError> {
-                if args.len() != 2 {
-                    return Err(eval_err("[spookyAdd] Wrong number of arguments"));
-                }
-                let a = env
-                    .eval(&args[0])?
-                    .get_int()
-                    .ok_or(eval_err("[spookyAdd] Wrong argument type"))?;
-                let b = env
-                    .eval(&args[1])?
-                    .get_int()
-                    .ok_or(eval_err("[spookyAdd] Wrong argument type"))?;
-                Ok(Integer(a + b))
-            },
-        ),
-        unsafe_func("lua", lua::run_lua_file_from_lisp_args),
+            &[],
+            Arity::Exactly(2),
+            Evaluation::Strict,
+            |args: &Args, _env: &LispEnv| -> Result<LispValue, EvalError> {
+                let sum = args.int(0)?.checked_add(args.int(1)?);
+                Ok(Integer(sum.ok_or_else(|| overflow(args))?))
+            },
+        ),
+        unsafe_func(
+            "lua",
+            &[Capability::Process],
+            Arity::Exactly(1),
+            Evaluation::Strict,
+            lua::run_lua_file_from_lisp_args,
+        ),
         unsafe_func(
             "readline",
-            |args: &[LispValue], _env: &LispEnv| -> Result<LispValue, EvalError> {
-                if args.len() != 1 {
-                    return Err(eval_err("[readline] Wrong number of arguments"));
-                }
-
+            &[Capability::Stdin],
+            Arity::Exactly(1),
+            Evaluation::Strict,
+            |args: &Args, env: &LispEnv| -> Result<LispValue, EvalError> {
                 let mut line = String::new();
 
                 std::io::stdin()
                     .read_line(&mut line)
-                    .expect("[readline] Failed to read line");
+                    .map_err(|e| args.error(format!("failed to read a line: {}", e)))?;
 
-                Ok(super::parse_string(&line)?)
+                let data = super::parse_string(&line)?;
+                env.charge_value(&data)?;
+                Ok(data)
             },
         ),
         unsafe_func(
             "readf",
-            |args: &[LispValue], env: &LispEnv| -> Result<LispValue, EvalError> {
+            &[Capability::FsRead],
+            Arity::Between(1, 3),
+            Evaluation::Strict,
+            |args: &Args, env: &LispEnv| -> Result<LispValue, EvalError> {
                 let filename = match args.len() {
-                    1 => {
-                        if let LispValue::Symbol(package) = env.eval(&args[0])? {
-                            format!("lisb/{}.l", package)
-                        } else {
-                            return Err(eval_err("[readf] Wrong argument type (single argument)"));
-                        }
-                    }
-                    2 => {
-                        if let LispValue::Symbol(main) = env.eval(&args[0])? {
-                            if let LispValue::Symbol(ext) = env.eval(&args[1])? {
-                                format!("lisb/{}.{}", main, ext)
-                            } else {
-                                return Err(eval_err("[readf] Wrong argument type (first of two)"));
-                            }
-                        } else {
-                            return Err(eval_err("[readf] Wrong argument type (second of two)"));
-                        }
-                    }
-                    3 => {
-                        if let LispValue::Symbol(folder) = env.eval(&args[0])? {
-                            if let LispValue::Symbol(main) = env.eval(&args[1])? {
-                                if let LispValue::Symbol(ext) = env.eval(&args[2])? {
-                                    format!("{}/{}.{}", folder, main, ext)
-                                } else {
-                                    return Err(eval_err(
-                                        "[readf] Wrong argument type (first of three)",
-                                    ));
-                                }
-                            } else {
-                                return Err(eval_err(
-                                    "[readf] Wrong argument type (second of three)",
-                                ));
-                            }
-                        } else {
-                            return Err(eval_err("[readf] Wrong argument type (third of three)"));
-                        }
-                    }
-                    _ => return Err(eval_err("[readf] Wrong number of arguments")),
+                    1 => format!("lisb/{}.l", args.symbol(0)?),
+                    2 => format!("lisb/{}.{}", args.symbol(0)?, args.symbol(1)?),
+                    _ => format!(
+                        "{}/{}.{}",
+                        args.symbol(0)?,
+                        args.symbol(1)?,
+                        args.symbol(2)?
+                    ),
                 };
-                let source = std::fs::read_to_string(&filename).expect("[readf] IO error");
-                let data = crate::parse_string(&source).expect("[readf] Parse error");
-                //~ Ok(env.eval(&data)?)
+                env.capabilities().check_read(&filename)?;
+                let source = std::fs::read_to_string(&filename)
+                    .map_err(|e| args.error(format!("can't read {}: {}", filename, e)))?;
+                let data = crate::parse_string(&source)
+                    .map_err(|e| args.error(format!("can't parse {}: {}", filename, e)))?;
+                env.charge_value(&data)?;
                 Ok(data)
             },
         ),
         unsafe_func(
             "include",
-            |args: &[LispValue], env: &LispEnv| -> Result<LispValue, EvalError> {
-                if args.len() != 2 {
-                    eprintln!("{} args", args.len());
-                    return Err(eval_err("[include] Wrong number of arguments"));
-                }
-
-                /*
-                let filename = if let LispValue::Symbol(package) = env.eval(&args[0])? {
-                    format!("lisb/{}.l", package)
-                } else {
-                    return Err(eval_err("[include] Wrong argument type (first of three)"));
+            &[Capability::FsRead],
+            Arity::Exactly(2),
+            Evaluation::Lazy,
+            |args: &Args, env: &LispEnv| -> Result<LispValue, EvalError> {
+                let source = format!("(let (readf {}) {})", &args[0], &args[1]);
+                Ok(crate::parse_eval(&source, env)?)
+            },
+        ),
+        strict(
+            "macroexpand-1",
+            Arity::Exactly(1),
+            |args: &Args, env: &LispEnv| -> Result<LispValue, EvalError> {
+                let (expansion, expanded) = env.macro_expand_1(&args[0])?;
+                let result = LispValue::List(vec![expansion, expanded.into()].into());
+                env.charge_value(&result)?;
+                Ok(result)
+            },
+        ),
+        strict(
+            "macroexpand-all",
+            Arity::Exactly(1),
+            |args: &Args, env: &LispEnv| -> Result<LispValue, EvalError> {
+                let expansion = env.macro_eval(&args[0])?;
+                env.charge_value(&expansion)?;
+                Ok(expansion)
+            },
+        ),
+        strict(
+            "gensym",
+            Arity::Between(0, 1),
+            |args: &Args, env: &LispEnv| -> Result<LispValue, EvalError> {
+                let prefix = match args.len() {
+                    0 => SymbolValue::new("g"),
+                    _ => args.symbol(0)?,
                 };
-
-                let source = std::fs::read_to_string(&filename).expect("[include] IO error");
-                let data = crate::parse_string(&source).expect("[include] Parse error");
-                &data
-                */
-
-                match crate::parse_eval(&format!("(let (readf {}) {})", &args[0], &args[1]), &env) {
-                    Err(e) => panic!("{}", e),
-                    Ok(ok) => Ok(ok),
-                }
+                let sym = gensym(&prefix.to_string());
+                env.charge_value(&sym)?;
+                Ok(sym)
             },
         ),
-        func(
+        lazy(
             "expand",
-            |args: &[LispValue], env: &LispEnv| -> Result<LispValue, EvalError> {
-                if args.len() < 1 {
-                    return Err(eval_err("[expand] Wrong number of arguments"));
-                }
-
+            Arity::AtLeast(1),
+            |args: &Args, env: &LispEnv| -> Result<LispValue, EvalError> {
                 let mac = env.eval(&args[0])?;
-
-                let mac_v = mac.clone();
-
                 let mac = mac
                     .get_macro()
-                    .ok_or(eval_err("[expand] Wrong argument type"))?;
+                    .ok_or_else(|| args.error(format!("expected a macro, got {}", mac)))?;
 
-                let call = {
-                    let mut v = vec![mac_v];
-                    v.extend_from_slice(&args[1..]);
-                    v.into()
-                };
-
-                // note that the entire call, including macro, is passed in
-                Ok(match mac.expand_body(&mac.body.fallible_clone()?, &call) {
-                    Ok(v) => v,
-                    _ => return Err(eval_err("[expand] failure to expand macro call")),
-                })
-
-                //Ok(LispValue::nil())
+                env.macro_expand(mac, &args[1..])
             },
         ),
-        /*
-        func(
-            "",
-            |_args: &[LispValue], _env: &LispEnv| -> Result<LispValue, EvalError> {
-                todo!()
-            },
-        ),
-        */
     ]);
 
+    // the old names of `<` and `>`, and scheme's name for `do`
+    for (alias, name) in [("lt", "<"), ("gt", ">"), ("begin", DO)] {
+        let builtin = bindings[&SymbolValue::new(name)].clone();
+        bindings.insert(alias.into(), builtin);
+    }
+
     LispEnv::from_hashmap(bindings)
 }
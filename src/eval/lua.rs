@@ -1,13 +1,14 @@
-use crate::eval::eval_err;
+use crate::builtin::Args;
 use crate::eval::EvalError;
 use crate::eval::LispEnv;
-use crate::value::Value as LispValue;
+use crate::value::{ListValue, Value as LispValue};
 
 use std::process::Command;
-use std::sync::Arc;
 
 pub fn run(mut child: std::process::Child) -> Result<LispValue, EvalError> {
-    let error_status = child.wait().expect("failed to wait on child");
+    let error_status = child
+        .wait()
+        .map_err(|e| EvalError::String(format!("[lua] failed to wait on lua: {}", e)))?;
 
     let code = error_status.code();
 
@@ -17,7 +18,7 @@ pub fn run(mut child: std::process::Child) -> Result<LispValue, EvalError> {
     };
 
     // list!(error_code, "unimplemented")
-    Ok(LispValue::List(Arc::new([
+    Ok(LispValue::List(ListValue::from([
         error_code.into(),
         "unimplemented".into(),
     ])))
@@ -28,7 +29,7 @@ pub fn _run_lua_source(src: &str) -> Result<LispValue, EvalError> {
         .arg("-e")
         .arg(&src)
         .spawn()
-        .expect("Lua failed to start");
+        .map_err(failed_to_start)?;
 
     run(child)
 }
@@ -37,26 +38,19 @@ pub fn run_lua_file(filename: &str) -> Result<LispValue, EvalError> {
     let child = Command::new("lua")
         .arg(&filename)
         .spawn()
-        .expect("Lua failed to start");
+        .map_err(failed_to_start)?;
 
     run(child)
 }
 
-pub fn run_lua_file_from_lisp_args(
-    args: &[LispValue],
-    env: &LispEnv,
-) -> Result<LispValue, EvalError> {
-    if args.len() != 1 {
-        return Err(eval_err("[lua] Wrong number of arguments"));
-    }
-
-    let sym = env.eval(&args[0])?;
+fn failed_to_start(e: std::io::Error) -> EvalError {
+    EvalError::String(format!("[lua] lua failed to start: {}", e))
+}
 
-    let lib_name = sym
-        .get_symbol()
-        .ok_or(eval_err("argument to lua not a symbol"))?;
+pub fn run_lua_file_from_lisp_args(args: &Args, _env: &LispEnv) -> Result<LispValue, EvalError> {
+    let lib_name = args.symbol(0)?;
 
-    let filename = get_lua_lib_filename(lib_name);
+    let filename = get_lua_lib_filename(&lib_name);
 
     let result = run_lua_file(&filename)?;
 
@@ -0,0 +1,235 @@
+use super::value::Value;
+use std::sync::{Arc, OnceLock};
+
+// Persistent lists
+//
+// a list is either a run of elements that were built all at once, like parsed
+// code or the result of `map`, or an element consed onto another list. `cons`,
+// `car` and `cdr` share whatever they can, so walking or building a list one
+// element at a time doesn't copy it. code that wants the elements side by side
+// still gets a slice, which a consed list has to copy out once, so anything
+// running under a budget should walk the list or go through `LispEnv::slice`
+#[derive(Clone, Default)]
+pub struct ListValue(Repr);
+
+#[derive(Clone, Default)]
+enum Repr {
+    #[default]
+    Empty,
+    // the elements from `start` on
+    Run {
+        items: Arc<[Value]>,
+        start: usize,
+    },
+    Cons(Arc<Cell>),
+}
+
+struct Cell {
+    head: Value,
+    tail: ListValue,
+    len: usize,
+    // the whole list in one piece, made the first time a slice is wanted
+    flat: OnceLock<Arc<[Value]>>,
+}
+
+impl ListValue {
+    // roughly what one `cons` allocates, for charging budgets
+    pub const CELL_SIZE: usize = std::mem::size_of::<Cell>();
+
+    pub fn cons(&self, head: Value) -> ListValue {
+        ListValue(Repr::Cons(Arc::new(Cell {
+            head,
+            tail: self.clone(),
+            len: self.len() + 1,
+            flat: OnceLock::new(),
+        })))
+    }
+
+    pub fn first(&self) -> Option<&Value> {
+        match &self.0 {
+            Repr::Empty => None,
+            Repr::Run { items, start } => items.get(*start),
+            Repr::Cons(cell) => Some(&cell.head),
+        }
+    }
+
+    // everything after the first element, or the empty list
+    pub fn rest(&self) -> ListValue {
+        match &self.0 {
+            Repr::Run { items, start } if start + 1 < items.len() => ListValue(Repr::Run {
+                items: items.clone(),
+                start: start + 1,
+            }),
+            Repr::Empty | Repr::Run { .. } => ListValue::default(),
+            Repr::Cons(cell) => cell.tail.clone(),
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        match &self.0 {
+            Repr::Empty => 0,
+            Repr::Run { items, start } => items.len() - start,
+            Repr::Cons(cell) => cell.len,
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    // the elements in order, without putting them side by side first
+    pub fn iter(&self) -> Iter<'_> {
+        Iter {
+            list: Some(self),
+            run: [].iter(),
+        }
+    }
+
+    // the list from its `n`th element on, sharing it rather than copying
+    pub fn skip(&self, mut n: usize) -> ListValue {
+        let mut list = self;
+        while n > 0 {
+            match &list.0 {
+                Repr::Empty => break,
+                Repr::Run { items, start } => {
+                    return match start.saturating_add(n) {
+                        start if start < items.len() => ListValue(Repr::Run {
+                            items: items.clone(),
+                            start,
+                        }),
+                        _ => ListValue::default(),
+                    };
+                }
+                Repr::Cons(cell) => list = &cell.tail,
+            }
+            n -= 1;
+        }
+        list.clone()
+    }
+
+    pub fn nth(&self, mut n: usize) -> Option<&Value> {
+        let mut list = self;
+        loop {
+            match &list.0 {
+                Repr::Empty => return None,
+                Repr::Run { items, start } => return items.get(start.saturating_add(n)),
+                Repr::Cons(cell) if n == 0 => return Some(&cell.head),
+                Repr::Cons(cell) => list = &cell.tail,
+            }
+            n -= 1;
+        }
+    }
+
+    // whether taking a slice of the list is free, rather than a copy
+    pub fn is_flat(&self) -> bool {
+        match &self.0 {
+            Repr::Empty | Repr::Run { .. } => true,
+            Repr::Cons(cell) => cell.flat.get().is_some(),
+        }
+    }
+
+    fn as_slice(&self) -> &[Value] {
+        match &self.0 {
+            Repr::Empty => &[],
+            Repr::Run { items, start } => &items[*start..],
+            Repr::Cons(cell) => cell.flat.get_or_init(|| self.iter().cloned().collect()),
+        }
+    }
+}
+
+impl Drop for Cell {
+    // a long list is taken apart one cell at a time, rather than by recursing
+    // down to its end
+    fn drop(&mut self) {
+        let mut tail = std::mem::take(&mut self.tail);
+        while let Repr::Cons(cell) = std::mem::take(&mut tail.0) {
+            match Arc::try_unwrap(cell) {
+                Ok(mut cell) => tail = std::mem::take(&mut cell.tail),
+                Err(_) => break,
+            }
+        }
+    }
+}
+
+pub struct Iter<'a> {
+    list: Option<&'a ListValue>,
+    run: std::slice::Iter<'a, Value>,
+}
+
+impl<'a> Iterator for Iter<'a> {
+    type Item = &'a Value;
+
+    fn next(&mut self) -> Option<&'a Value> {
+        if let Some(value) = self.run.next() {
+            return Some(value);
+        }
+        match &self.list?.0 {
+            Repr::Empty => None,
+            Repr::Run { items, start } => {
+                self.list = None;
+                self.run = items[*start..].iter();
+                self.run.next()
+            }
+            Repr::Cons(cell) => {
+                self.list = Some(&cell.tail);
+                Some(&cell.head)
+            }
+        }
+    }
+}
+
+impl core::ops::Deref for ListValue {
+    type Target = [Value];
+
+    fn deref(&self) -> &[Value] {
+        self.as_slice()
+    }
+}
+
+impl PartialEq for ListValue {
+    fn eq(&self, other: &ListValue) -> bool {
+        self.len() == other.len() && self.iter().eq(other.iter())
+    }
+}
+
+impl From<Vec<Value>> for ListValue {
+    fn from(items: Vec<Value>) -> ListValue {
+        ListValue::from(Arc::<[Value]>::from(items))
+    }
+}
+
+impl From<&[Value]> for ListValue {
+    fn from(items: &[Value]) -> ListValue {
+        ListValue::from(Arc::<[Value]>::from(items))
+    }
+}
+
+impl<const N: usize> From<[Value; N]> for ListValue {
+    fn from(items: [Value; N]) -> ListValue {
+        ListValue::from(Arc::<[Value]>::from(items))
+    }
+}
+
+impl From<Arc<[Value]>> for ListValue {
+    fn from(items: Arc<[Value]>) -> ListValue {
+        if items.is_empty() {
+            return ListValue::default();
+        }
+        ListValue(Repr::Run { items, start: 0 })
+    }
+}
+
+impl FromIterator<Value> for ListValue {
+    fn from_iter<I: IntoIterator<Item = Value>>(iter: I) -> ListValue {
+        ListValue::from(iter.into_iter().collect::<Arc<[Value]>>())
+    }
+}
+
+impl<'a> IntoIterator for &'a ListValue {
+    type Item = &'a Value;
+    type IntoIter = Iter<'a>;
+
+    fn into_iter(self) -> Iter<'a> {
+        self.iter()
+    }
+}
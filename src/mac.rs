@@ -1,111 +1,257 @@
-use std::error::Error;
+use std::collections::HashMap;
 use std::sync::Arc;
 
-use super::value::ListValue;
+use super::eval::EvalError;
 use super::value::Value;
+use super::value::{ListValue, SymbolValue};
 
 #[derive(Clone)]
-pub struct MacroValue {
-    pub params: ListValue,
-    pub body: Arc<Value>,
+pub enum MacroValue {
+    // a body that is evaluated with its params bound to the argument forms
+    Procedural { params: ListValue, body: Arc<Value> },
+    // a list of patterns, the first one to match the call picks the template
+    Rules(Arc<SyntaxRules>),
 }
 
 impl MacroValue {
     pub fn new(params: ListValue, body: Arc<Value>) -> MacroValue {
-        MacroValue { params, body }
+        MacroValue::Procedural { params, body }
     }
+}
+
+// a symbol that hasn't been used anywhere else
+pub fn gensym(prefix: &str) -> Value {
+    Value::Symbol(SymbolValue::fresh(prefix))
+}
+
+// Pattern-based macros
+//
+// (syntax-rules (literal ...) ((_ pattern ...) template) ...)
+//
+// in a pattern, `_` matches anything, a literal matches only itself, and any
+// other symbol matches anything and binds it for the template. `x ...` after a
+// pattern matches it any number of times, and the same `...` after part of the
+// template repeats it once for each match
+
+const ELLIPSIS: &str = "...";
+
+pub struct SyntaxRules {
+    literals: Vec<SymbolValue>,
+    rules: Vec<(Value, Value)>,
+}
 
-    pub fn get<'a>(&self, args: &'a [Value], pattern: &Value) -> Option<&'a Value> {
-        if let Some(index) = self.params.iter().position(|s| s == pattern) {
-            Some(&args[index])
-        } else {
-            None
+#[derive(Clone)]
+enum Match {
+    One(Value),
+    Many(Vec<Match>),
+}
+
+type Matches = HashMap<SymbolValue, Match>;
+
+impl SyntaxRules {
+    pub fn new(literals: &Value, rules: &[Value]) -> Result<SyntaxRules, EvalError> {
+        let literals = literals
+            .get_list()
+            .filter(|_| literals.is_list_of_symbols())
+            .ok_or(EvalError::Static(
+                "[syntax-rules] literals must be a list of symbols",
+            ))?
+            .iter()
+            .filter_map(Value::get_symbol_value)
+            .collect();
+
+        let rules = rules
+            .iter()
+            .map(|rule| match rule.get_list() {
+                // the macro's own name in the pattern is never looked at
+                Some([Value::List(pattern), template]) if !pattern.is_empty() => {
+                    Ok((Value::List(pattern[1..].to_vec().into()), template.clone()))
+                }
+                _ => Err(EvalError::String(format!(
+                    "[syntax-rules] a rule must look like ((_ pattern ...) template): {}",
+                    rule
+                ))),
+            })
+            .collect::<Result<_, _>>()?;
+
+        Ok(SyntaxRules { literals, rules })
+    }
+
+    pub fn expand(&self, args: &[Value]) -> Result<Value, EvalError> {
+        let args = Value::List(args.to_vec().into());
+        for (pattern, template) in self.rules.iter() {
+            let mut matches = Matches::new();
+            if self.match_pattern(pattern, &args, &mut matches) {
+                return instantiate(template, &matches);
+            }
         }
+        Err(EvalError::String(format!(
+            "[syntax-rules] no pattern matches the arguments {}",
+            args
+        )))
     }
 
-    fn expand_symbol<'a>(&'a self, args: &'a [Value], symbol: &'a Value) -> &'a Value {
-        match self.get(args, symbol) {
-            Some(arg) => arg, // just passing the argument through unchanged
-            None => symbol,
+    fn match_pattern(&self, pattern: &Value, form: &Value, matches: &mut Matches) -> bool {
+        match pattern {
+            Value::Symbol(s) if &**s == "_" => true,
+            Value::Symbol(s) if self.literals.contains(s) => form.get_symbol_value() == Some(*s),
+            Value::Symbol(s) => {
+                matches.insert(*s, Match::One(form.clone()));
+                true
+            }
+            Value::List(pattern) => match form.get_list() {
+                Some(forms) => self.match_list(pattern, forms, matches),
+                None => false,
+            },
+            _ => pattern == form,
         }
     }
 
-    pub fn expand_body(&self, body: &Value, call: &ListValue) -> Result<Value, Box<dyn Error>> {
-        use Value::*;
+    fn match_list(&self, pattern: &[Value], forms: &[Value], matches: &mut Matches) -> bool {
+        let ellipsis = pattern.iter().position(is_ellipsis);
 
-        let args = &call[1..];
-        // TODO: check at some point that params.len() == args.len()
+        let (before, repeated, after) = match ellipsis {
+            Some(i) if i > 0 => (&pattern[..i - 1], Some(&pattern[i - 1]), &pattern[i + 1..]),
+            _ => (pattern, None, &pattern[pattern.len()..]),
+        };
 
-        let env = super::eval::default_env();
+        let repeated = match repeated {
+            Some(repeated) => repeated,
+            None => {
+                return pattern.len() == forms.len()
+                    && pattern
+                        .iter()
+                        .zip(forms)
+                        .all(|(p, f)| self.match_pattern(p, f, matches))
+            }
+        };
 
-        Ok(match body {
-            Macro(_) => todo!("macros making macros not implemented"),
-            Symbol(ref _sym) => match self.get(args, &body) {
-                Some(arg) => arg.fallible_clone()?,
-                None => body.fallible_clone()?,
-            },
-            List(list) => {
-                let mut new_list = vec![];
-                if list.len() >= 1 {
-                    let f = {
-                        let f = &list[0];
-                        match f {
-                            Symbol(_) => env.eval(f)?,
-                            _ => f.clone(),
-                        }
-                    };
-                    match f {
-                        Macro(_) => todo!(),
-                        _ => {
-                            new_list.push(f.clone());
-                            for val in list[1..].iter() {
-                                new_list.push(self.expand_body(val, call)?);
-                            }
-                        }
-                    }
-                }
-                List(new_list.into())
+        if forms.len() < before.len() + after.len() {
+            return false;
+        }
+
+        let middle = &forms[before.len()..forms.len() - after.len()];
+
+        let fixed = before
+            .iter()
+            .zip(forms)
+            .chain(after.iter().zip(&forms[forms.len() - after.len()..]))
+            .all(|(p, f)| self.match_pattern(p, f, matches));
+        if !fixed {
+            return false;
+        }
+
+        let mut each = vec![];
+        for form in middle {
+            let mut inner = Matches::new();
+            if !self.match_pattern(repeated, form, &mut inner) {
+                return false;
             }
-            UnsafeCall(_) => todo!(),
-            Bool(_) | Integer(_) | Func(_) | UnsafeFunc(_) | Lambda(_) => body.fallible_clone()?,
-        })
+            each.push(inner);
+        }
+
+        // every variable under the ellipsis gets one match per repetition
+        let mut vars = vec![];
+        pattern_vars(repeated, &self.literals, &mut vars);
+        for var in vars {
+            let seq = each
+                .iter_mut()
+                .map(|m| m.remove(&var).unwrap_or(Match::Many(vec![])))
+                .collect();
+            matches.insert(var, Match::Many(seq));
+        }
+
+        true
     }
+}
+
+fn is_ellipsis(val: &Value) -> bool {
+    val.get_symbol() == Some(ELLIPSIS)
+}
 
-    pub fn expand_recurse(val: &Value) -> Result<Value, Box<dyn Error>> {
-        use Value::*;
+fn pattern_vars(pattern: &Value, literals: &[SymbolValue], vars: &mut Vec<SymbolValue>) {
+    match pattern {
+        Value::Symbol(s) if &**s == "_" || &**s == ELLIPSIS => (),
+        Value::Symbol(s) if literals.contains(s) => (),
+        Value::Symbol(s) => vars.push(*s),
+        Value::List(list) => list.iter().for_each(|p| pattern_vars(p, literals, vars)),
+        _ => (),
+    }
+}
 
-        eprint!("UUUHHHHH\r\n");
-        Ok(match val {
-            Bool(_) | Integer(_) | Macro(_) | Func(_) | UnsafeFunc(_) | Lambda(_) | Symbol(_) => {
-                val.fallible_clone()?
+fn instantiate(template: &Value, matches: &Matches) -> Result<Value, EvalError> {
+    match template {
+        Value::Symbol(s) => match matches.get(s) {
+            Some(Match::One(v)) => Ok(v.clone()),
+            Some(Match::Many(_)) => Err(EvalError::String(format!(
+                "[syntax-rules] `{}` needs to be followed by `...`",
+                s
+            ))),
+            // anything else is the macro's own
+            None => Ok(template.clone()),
+        },
+        Value::List(list) => {
+            // `(... ...)` stands for a literal `...`
+            if let [first, second] = &list[..] {
+                if is_ellipsis(first) && is_ellipsis(second) {
+                    return Ok(first.clone());
+                }
             }
-            UnsafeCall(_) => todo!(),
-            List(list) => {
-                if val.is_nil() {
-                    val.fallible_clone()?
+
+            let mut result = vec![];
+            let mut i = 0;
+            while i < list.len() {
+                let repeats = list.get(i + 1).is_some_and(is_ellipsis);
+                if repeats {
+                    result.extend(instantiate_many(&list[i], matches)?);
+                    i += 2;
                 } else {
-                    let f = &list[0];
-                    let _args = &list[1..];
-                    match f {
-                        Macro(mac) => mac.expand_body(&mac.body.clone(), &list)?,
-                        _ => {
-                            let mut new_list = vec![];
-
-                            let first = match f {
-                                Symbol(sym) => super::eval::default_env().eval(f)?,
-                                _ => f.clone(),
-                            };
-                            
-                            new_list.push(first);
-                            for v in list[1..].iter() {
-                                new_list.push(MacroValue::expand_recurse(v)?);
-                                //new_list.push(9001.into());
-                            }
-                            List(new_list.into())
-                        }
-                    }
+                    result.push(instantiate(&list[i], matches)?);
+                    i += 1;
                 }
             }
+            Ok(Value::List(result.into()))
+        }
+        _ => Ok(template.clone()),
+    }
+}
+
+// a template followed by `...`, once for each match of the variables in it
+fn instantiate_many(template: &Value, matches: &Matches) -> Result<Vec<Value>, EvalError> {
+    let mut names = vec![];
+    pattern_vars(template, &[], &mut names);
+
+    let repeated = names
+        .iter()
+        .filter_map(|name| match matches.get(name) {
+            Some(Match::Many(seq)) => Some((name, seq)),
+            _ => None,
         })
+        .collect::<Vec<_>>();
+
+    let len = match repeated.first() {
+        Some((_, seq)) => seq.len(),
+        None => {
+            return Err(EvalError::String(format!(
+                "[syntax-rules] nothing to repeat in {} ...",
+                template
+            )))
+        }
+    };
+    if repeated.iter().any(|(_, seq)| seq.len() != len) {
+        return Err(EvalError::String(format!(
+            "[syntax-rules] the variables in {} ... matched different numbers of times",
+            template
+        )));
     }
+
+    (0..len)
+        .map(|i| {
+            let mut inner = matches.clone();
+            for (name, seq) in repeated.iter() {
+                inner.insert(**name, seq[i].clone());
+            }
+            instantiate(template, &inner)
+        })
+        .collect()
 }
@@ -1,9 +1,16 @@
+mod budget;
+mod builtin;
+mod capability;
 mod cli;
 mod env;
 mod eval;
+mod list;
 mod mac;
+mod matching;
+mod params;
 mod parse;
 mod scan;
+mod symbol;
 mod tests;
 mod value;
 
@@ -34,10 +41,8 @@ pub fn parse_eval(source: &str, env: &LispEnv) -> Result<LispValue, Box<dyn Erro
 
 pub fn parse_macro_pass_and_eval(source: &str, env: &LispEnv) -> Result<LispValue, Box<dyn Error>> {
     let result = parse_string(source)?;
-    let passed = mac::MacroValue::expand_recurse(&result)?;
-    //    let passed = env.macro_eval(&result)?;
+    let passed = env.macro_eval(&result)?;
     Ok(env.eval(&passed)?)
-    //Ok(passed)
 }
 
 pub fn parse_string(source: &str) -> Result<LispValue, Box<dyn Error>> {
@@ -140,6 +145,9 @@ fn main() -> Result<(), Box<dyn Error>> {
     println!("---");
     println!();
 
+    let capable_env = env.new_capable_env(args.capabilities());
+    let env = &capable_env;
+
     let session = Session::new();
 
     ////////////////////////////////////////////////////////////////
@@ -174,11 +182,26 @@ fn main() -> Result<(), Box<dyn Error>> {
                     //    break 'main Ok(());
                 }
 
-                let result = parse_macro_pass_and_eval(&line, env);
+                // show what a form turns into once every macro in it is expanded
+                if let Some(source) = line.strip_prefix(":expand") {
+                    match parse_string(source).and_then(|form| Ok(env.macro_eval(&form)?)) {
+                        Ok(expansion) => {
+                            print!("\r{}\r\n", expansion.pretty(72).replace('\n', "\r\n"))
+                        }
+                        Err(e) => print_error(&session, &format!("\r[ERROR] {}\r\n", e))?,
+                    }
+                    rl.history_mut().add(line);
+                    continue;
+                }
+
+                let result = match args.budget() {
+                    Some(budget) => parse_macro_pass_and_eval(line, &env.new_budgeted_env(budget)),
+                    None => parse_macro_pass_and_eval(&line, env),
+                };
                 match result {
                     Ok(res) => {
                         print!("\r => {}\r\n", res);
-                        if res == LispValue::Symbol("exit".to_string()) {
+                        if res == LispValue::Symbol("exit".into()) {
                             break 'main Ok(());
                         }
                     }
@@ -232,6 +255,9 @@ fn old_main() -> Result<(), Box<dyn Error>> {
     println!("---");
     println!();
 
+    let capable_env = env.new_capable_env(args.capabilities());
+    let env = &capable_env;
+
     let session = Session::new();
 
     let mut command_buffer = String::new();
@@ -300,11 +326,14 @@ fn old_main() -> Result<(), Box<dyn Error>> {
                                     break 'main Ok(());
                                 }
 
-                                let result = parse_eval(&line, env);
+                                let result = match args.budget() {
+                                    Some(budget) => parse_eval(line, &env.new_budgeted_env(budget)),
+                                    None => parse_eval(&line, env),
+                                };
                                 match result {
                                     Ok(res) => {
                                         print!(" => {}\r\n", res);
-                                        if res == LispValue::Symbol("exit".to_string()) {
+                                        if res == LispValue::Symbol("exit".into()) {
                                             break 'main Ok(());
                                         }
                                     }
@@ -447,7 +476,7 @@ fn old_main() -> Result<(), Box<dyn Error>> {
         match result {
             Ok(res) => {
                 println!("=> {}", res);
-                if res == LispValue::Symbol("exit".to_string()) {
+                if res == LispValue::Symbol("exit".into()) {
                     break 'main Ok(());
                 }
             }
@@ -0,0 +1,160 @@
+use super::env::{Bindings, LispEnv};
+use super::eval::EvalError;
+use super::params::REST;
+use super::value::{SymbolValue, Value};
+
+// the head of a pattern that only matches values a predicate accepts
+pub const GUARD: &str = "?";
+
+// Patterns for `match`
+//
+// `_` matches anything, `x` matches anything and binds it, and numbers,
+// `true`, `false`, `()`, keywords and quoted data match only themselves.
+// `(a b & rest)` matches a list of at least two elements, and `(? pred pat)`
+// matches whatever `pat` does as long as `(pred value)` is truthy. a name that
+// shows up twice has to match equal values both times
+#[derive(Debug, Clone)]
+pub enum MatchPattern {
+    Wildcard,
+    Bind(SymbolValue),
+    Literal(Value),
+    List {
+        items: Vec<MatchPattern>,
+        rest: Option<Box<MatchPattern>>,
+    },
+    Guard {
+        pred: Value,
+        pattern: Box<MatchPattern>,
+    },
+}
+
+impl MatchPattern {
+    pub fn parse(pattern: &Value) -> Result<MatchPattern, EvalError> {
+        use MatchPattern::*;
+
+        Ok(match pattern {
+            Value::Symbol(s) => match &**s {
+                "_" => Wildcard,
+                "true" => Literal(Value::Bool(true)),
+                "false" => Literal(Value::Bool(false)),
+                REST | GUARD => {
+                    return Err(EvalError::String(format!(
+                        "[match] `{}` can't be used as a pattern by itself",
+                        s
+                    )))
+                }
+                _ if pattern.is_keyword() => Literal(pattern.clone()),
+                _ => Bind(*s),
+            },
+            Value::List(list) => match &list[..] {
+                [quote, data] if quote.get_symbol() == Some("quote") => Literal(data.clone()),
+                [guard, pred, pattern] if guard.get_symbol() == Some(GUARD) => Guard {
+                    pred: pred.clone(),
+                    pattern: Box::new(MatchPattern::parse(pattern)?),
+                },
+                [guard, pred] if guard.get_symbol() == Some(GUARD) => Guard {
+                    pred: pred.clone(),
+                    pattern: Box::new(Wildcard),
+                },
+                _ => {
+                    let mut items = vec![];
+                    let mut rest = None;
+                    let mut iter = list.iter();
+                    while let Some(item) = iter.next() {
+                        if item.get_symbol() != Some(REST) {
+                            items.push(MatchPattern::parse(item)?);
+                            continue;
+                        }
+                        rest = match (iter.next(), iter.next()) {
+                            (Some(rest), None) => Some(Box::new(MatchPattern::parse(rest)?)),
+                            _ => {
+                                return Err(EvalError::String(format!(
+                                    "[match] `{}` must be followed by exactly one pattern: {}",
+                                    REST, pattern
+                                )))
+                            }
+                        };
+                    }
+                    List { items, rest }
+                }
+            },
+            _ => Literal(pattern.clone()),
+        })
+    }
+
+    // every name the pattern binds when it matches
+    pub fn names(&self) -> Vec<SymbolValue> {
+        let mut names = vec![];
+        self.collect_names(&mut names);
+        names
+    }
+
+    fn collect_names(&self, names: &mut Vec<SymbolValue>) {
+        use MatchPattern::*;
+
+        match self {
+            Bind(name) => names.push(*name),
+            List { items, rest } => {
+                items.iter().for_each(|item| item.collect_names(names));
+                rest.iter().for_each(|rest| rest.collect_names(names));
+            }
+            Guard { pattern, .. } => pattern.collect_names(names),
+            Wildcard | Literal(_) => (),
+        }
+    }
+
+    // predicates are evaluated in `env`, which is where the `match` is
+    pub fn matches(
+        &self,
+        value: &Value,
+        env: &LispEnv,
+        bindings: &mut Bindings,
+    ) -> Result<bool, EvalError> {
+        use MatchPattern::*;
+
+        match self {
+            Wildcard => Ok(true),
+            Bind(name) => match bindings.get(name) {
+                Some(bound) => Ok(bound == value),
+                None => {
+                    bindings.insert(*name, value.clone());
+                    Ok(true)
+                }
+            },
+            Literal(literal) => Ok(literal == value),
+            List { items, rest } => {
+                let values = match value.get_list_value() {
+                    Some(values) => values,
+                    None => return Ok(false),
+                };
+                let count_ok = match rest {
+                    Some(_) => values.len() >= items.len(),
+                    None => values.len() == items.len(),
+                };
+                if !count_ok {
+                    return Ok(false);
+                }
+                for (item, value) in items.iter().zip(values.iter()) {
+                    if !item.matches(value, env, bindings)? {
+                        return Ok(false);
+                    }
+                }
+                match rest {
+                    // shares the rest of the list, so there's nothing new to charge for
+                    Some(rest) => {
+                        rest.matches(&Value::List(values.skip(items.len())), env, bindings)
+                    }
+                    None => Ok(true),
+                }
+            }
+            Guard { pred, pattern } => {
+                let f = env.eval(pred)?;
+                let accepted = env.call(&f, std::slice::from_ref(value))?;
+                match accepted.is_truthy() {
+                    true => pattern.matches(value, env, bindings),
+                    false => Ok(false),
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,516 @@
+use super::env::Bindings;
+use super::eval::{EvalError, DO_BUILTIN};
+use super::value::{SymbolValue, Value};
+
+// the marker before the parameter that collects any remaining arguments
+pub const REST: &str = "&";
+// the marker before parameters that may be left out
+pub const OPTIONAL: &str = "&optional";
+// the marker before parameters that are passed as `:name value`
+pub const KEY: &str = "&key";
+// the builtin that runs the forms of a body one after the other
+pub const DO: &str = "do";
+// the builtin that binds a name for the rest of the body it is in
+pub const DEF: &str = "def";
+// the marker before the clauses of a `fn` with one for each arity
+pub const CLAUSES: &str = ":clauses";
+
+// a name, or a list of patterns that takes a list apart
+//
+// `((a b) & rest)` matched against `((1 2) 3 4)` binds `a` to 1, `b` to 2 and
+// `rest` to `(3 4)`
+#[derive(Debug, Clone)]
+pub enum Pattern {
+    Name(SymbolValue),
+    List {
+        items: Vec<Pattern>,
+        rest: Option<SymbolValue>,
+        source: Value,
+    },
+}
+
+impl Pattern {
+    pub fn parse(pattern: &Value) -> Result<Pattern, EvalError> {
+        let list = match pattern {
+            Value::Symbol(name) if !is_marker(pattern) => return Ok(Pattern::Name(*name)),
+            Value::List(list) => list,
+            _ => {
+                return Err(EvalError::String(format!(
+                    "expected a symbol or a list of patterns, got {}",
+                    pattern
+                )))
+            }
+        };
+
+        let mut items = vec![];
+        let mut rest = None;
+        let mut iter = list.iter();
+        while let Some(item) = iter.next() {
+            if item.get_symbol() == Some(REST) {
+                rest = Some(rest_name(iter.next(), iter.next(), pattern)?);
+            } else {
+                items.push(Pattern::parse(item)?);
+            }
+        }
+
+        Ok(Pattern::List {
+            items,
+            rest,
+            source: pattern.clone(),
+        })
+    }
+
+    pub fn names(&self) -> Vec<SymbolValue> {
+        let mut names = vec![];
+        self.collect_names(&mut names);
+        names
+    }
+
+    fn collect_names(&self, names: &mut Vec<SymbolValue>) {
+        match self {
+            Pattern::Name(name) => names.push(*name),
+            Pattern::List { items, rest, .. } => {
+                items.iter().for_each(|item| item.collect_names(names));
+                names.extend(rest.iter().cloned());
+            }
+        }
+    }
+
+    pub fn get_name(&self) -> Option<SymbolValue> {
+        match self {
+            Pattern::Name(name) => Some(*name),
+            Pattern::List { .. } => None,
+        }
+    }
+
+    pub fn bind(&self, value: Value, bindings: &mut Bindings) -> Result<(), EvalError> {
+        let (items, rest, source) = match self {
+            Pattern::Name(name) => {
+                bindings.insert(*name, value);
+                return Ok(());
+            }
+            Pattern::List {
+                items,
+                rest,
+                source,
+            } => (items, rest, source),
+        };
+
+        let values = value.get_list_value().ok_or(EvalError::String(format!(
+            "pattern {} expected a list, got {}",
+            source, value
+        )))?;
+
+        let count_ok = match rest {
+            Some(_) => values.len() >= items.len(),
+            None => values.len() == items.len(),
+        };
+        if !count_ok {
+            return Err(EvalError::String(format!(
+                "pattern {} expected {}{} elements, got {}",
+                source,
+                if rest.is_some() { "at least " } else { "" },
+                items.len(),
+                value
+            )));
+        }
+
+        for (item, value) in items.iter().zip(values.iter()) {
+            item.bind(value.clone(), bindings)?;
+        }
+        if let Some(rest) = rest {
+            bindings.insert(*rest, Value::List(values.skip(items.len())));
+        }
+        Ok(())
+    }
+}
+
+// the parameters of a `fn` or `macro`
+//
+// `(a b & rest)` binds `a` and `b` to the first two arguments, and `rest` to a
+// list of whatever arguments come after them. the required params can also be
+// patterns, so `((x y))` takes a single argument apart
+//
+// `(a &optional b (c 1) &key (width 10))` binds `b` and `c` to the next
+// arguments if there are any, and `width` to the argument after `:width`.
+// anything left out is bound to its default, which is evaluated with the
+// params before it in scope, or to `()` if it has none. with keyword params, a
+// keyword where an optional argument could go starts the keyword arguments.
+// `&key ((:size s) 2)` binds `s` to the argument after `:size`
+#[derive(Debug, Clone)]
+pub struct ParamList {
+    required: Vec<Pattern>,
+    optional: Vec<(SymbolValue, Option<Value>)>,
+    rest: Option<SymbolValue>,
+    // the keyword each is given with, like `:width`, and the name it's bound to
+    keys: Vec<(SymbolValue, SymbolValue, Option<Value>)>,
+}
+
+enum Section {
+    Required,
+    Optional,
+    Key,
+}
+
+impl ParamList {
+    pub fn parse(params: &Value) -> Result<ParamList, EvalError> {
+        let list = params
+            .get_list()
+            .ok_or(EvalError::Static("parameters must be a list"))?;
+
+        let mut required = vec![];
+        let mut optional = vec![];
+        let mut rest = None;
+        let mut keys = vec![];
+
+        let mut section = Section::Required;
+        let mut iter = list.iter();
+        while let Some(param) = iter.next() {
+            match (param.get_symbol(), &section) {
+                (Some(REST), Section::Key) => {
+                    return Err(EvalError::String(format!(
+                        "`{}` and `{}` can't be used together: {}",
+                        REST, KEY, params
+                    )))
+                }
+                (Some(REST), _) => rest = Some(rest_name(iter.next(), iter.next(), params)?),
+                (Some(OPTIONAL), Section::Required) => section = Section::Optional,
+                (Some(KEY), Section::Required | Section::Optional) => section = Section::Key,
+                (Some(OPTIONAL | KEY), _) => {
+                    return Err(EvalError::String(format!(
+                        "`{}` must come before `{}`, and each only once: {}",
+                        OPTIONAL, KEY, params
+                    )))
+                }
+                (_, Section::Required) => required.push(Pattern::parse(param)?),
+                (_, Section::Optional) => optional.push(with_default(param)?),
+                (_, Section::Key) => keys.push(key_param(param)?),
+            }
+        }
+
+        Ok(ParamList {
+            required,
+            optional,
+            rest,
+            keys,
+        })
+    }
+
+    // every name bound by these parameters
+    pub fn names(&self) -> Vec<SymbolValue> {
+        let required = self.required.iter().flat_map(Pattern::names);
+        let optional = self.optional.iter().map(|(name, _)| name).cloned();
+        let keys = self.keys.iter().map(|(_, name, _)| name).cloned();
+        required
+            .chain(optional)
+            .chain(self.rest.iter().cloned())
+            .chain(keys)
+            .collect()
+    }
+
+    // the names of the params, if they are nothing but a list of names
+    pub fn plain_names(&self) -> Option<Vec<SymbolValue>> {
+        if !self.optional.is_empty() || self.rest.is_some() || !self.keys.is_empty() {
+            return None;
+        }
+        self.required.iter().map(Pattern::get_name).collect()
+    }
+
+    // whether a call with this many arguments fits
+    pub fn accepts(&self, count: usize) -> bool {
+        let n = self.required.len();
+        let unbounded = self.rest.is_some() || !self.keys.is_empty();
+        count >= n && (unbounded || count <= n + self.optional.len())
+    }
+
+    // `eval_default` evaluates a default with the params bound so far
+    pub fn bind(
+        &self,
+        args: Vec<Value>,
+        mut eval_default: impl FnMut(&Value, &Bindings) -> Result<Value, EvalError>,
+    ) -> Result<Bindings, EvalError> {
+        let n = self.required.len();
+        let max = n + self.optional.len();
+        let unbounded = self.rest.is_some() || !self.keys.is_empty();
+        if !self.accepts(args.len()) {
+            let expected = match (unbounded, self.optional.is_empty()) {
+                (true, _) => format!("at least {}", n),
+                (false, false) => format!("{} to {}", n, max),
+                (false, true) => n.to_string(),
+            };
+            return Err(EvalError::String(format!(
+                "expected {} arguments, got {}",
+                expected,
+                args.len()
+            )));
+        }
+
+        let mut args = args.into_iter().peekable();
+        let mut bindings = Bindings::new();
+        for (param, arg) in self.required.iter().zip(args.by_ref()) {
+            param.bind(arg, &mut bindings)?;
+        }
+
+        // when there are keyword params, the first keyword ends the optional ones
+        let has_keys = !self.keys.is_empty();
+        for (name, default) in self.optional.iter() {
+            let value = match args.next_if(|arg| !(has_keys && arg.is_keyword())) {
+                Some(value) => value,
+                None => default_value(default, &bindings, &mut eval_default)?,
+            };
+            bindings.insert(*name, value);
+        }
+
+        if let Some(rest) = &self.rest {
+            bindings.insert(*rest, Value::List(args.collect::<Vec<_>>().into()));
+            return Ok(bindings);
+        }
+
+        // whatever is left is `:name value` pairs
+        let mut given = Bindings::new();
+        while let Some(key) = args.next() {
+            let keyword = match key.get_symbol_value() {
+                Some(keyword) if key.is_keyword() => keyword,
+                _ => {
+                    return Err(EvalError::String(format!(
+                        "expected a keyword argument, got {}",
+                        key
+                    )))
+                }
+            };
+            let name = match self.keys.iter().find(|(k, _, _)| *k == keyword) {
+                Some((_, name, _)) => *name,
+                None => {
+                    return Err(EvalError::String(format!(
+                        "unknown keyword argument {}",
+                        key
+                    )))
+                }
+            };
+            let value = args.next().ok_or(EvalError::String(format!(
+                "keyword argument {} is missing its value",
+                key
+            )))?;
+            if given.insert(name, value).is_some() {
+                return Err(EvalError::String(format!(
+                    "keyword argument {} was given twice",
+                    key
+                )));
+            }
+        }
+
+        for (_, name, default) in self.keys.iter() {
+            let value = match given.remove(name) {
+                Some(value) => value,
+                None => default_value(default, &bindings, &mut eval_default)?,
+            };
+            bindings.insert(*name, value);
+        }
+
+        Ok(bindings)
+    }
+}
+
+// the `(name value)` pairs of a `let`, either written out or quoted the way
+// they used to have to be
+pub fn let_pairs(bindings: &Value) -> Option<&[Value]> {
+    match bindings.get_list()? {
+        [quote, Value::List(pairs)] if quote.get_symbol() == Some("quote") => Some(pairs),
+        pairs if pairs.iter().all(Value::is_list) => Some(pairs),
+        _ => None,
+    }
+}
+
+// the forms of a body as one form, with several of them run by the `do`
+// builtin, which is put there itself so that a param named `do` can't stand in
+pub fn sequence(forms: &[Value]) -> Value {
+    match forms {
+        [form] => form.clone(),
+        _ => {
+            let mut list = vec![Value::Func(DO_BUILTIN)];
+            list.extend_from_slice(forms);
+            Value::List(list.into())
+        }
+    }
+}
+
+// the parts of a `fn` form, `(fn name (params) body ...)` with one clause or
+// `(fn name :clauses ((params) body ...) ...)` with one for each arity. the name
+// can be left out. without the marker the forms are always one clause, since
+// `((a b) (c d))` is also a list of patterns. a body of several forms is kept
+// as one `do` form
+pub struct FnForm<'a> {
+    pub name: Option<&'a Value>,
+    pub clauses: Vec<(&'a Value, Value)>,
+    // the `:clauses` marker, if there is one
+    marker: Option<&'a Value>,
+    // whether the forms would have been clauses, had the marker been there
+    unmarked_clauses: bool,
+}
+
+impl<'a> FnForm<'a> {
+    pub fn parse(args: &'a [Value]) -> Result<FnForm<'a>, EvalError> {
+        let (name, forms) = match args {
+            [name @ Value::Symbol(_), forms @ ..] if name.get_symbol() != Some(CLAUSES) => {
+                (Some(name), forms)
+            }
+            _ => (None, args),
+        };
+
+        match forms {
+            [marker, clauses @ ..] if marker.get_symbol() == Some(CLAUSES) => {
+                let clauses = clauses
+                    .iter()
+                    .map(|clause| match clause.get_list() {
+                        Some([params @ Value::List(_), body @ ..]) if !body.is_empty() => {
+                            Ok((params, sequence(body)))
+                        }
+                        _ => Err(EvalError::String(format!(
+                            "[fn] a clause must look like ((params) body ...): {}",
+                            clause
+                        ))),
+                    })
+                    .collect::<Result<Vec<_>, _>>()?;
+                if clauses.is_empty() {
+                    return Err(EvalError::Static("[fn] expected at least one clause"));
+                }
+                Ok(FnForm {
+                    name,
+                    clauses,
+                    marker: Some(marker),
+                    unmarked_clauses: false,
+                })
+            }
+            [params, body @ ..] if !body.is_empty() => Ok(FnForm {
+                name,
+                clauses: vec![(params, sequence(body))],
+                marker: None,
+                unmarked_clauses: forms
+                    .iter()
+                    .all(|form| matches!(form.get_list(), Some([Value::List(_), _, ..]))),
+            }),
+            _ => Err(EvalError::Static(
+                "[fn] expected (fn name (params) body ...) or (fn name :clauses ((params) body ...) ...)",
+            )),
+        }
+    }
+
+    // the params of each clause. when they don't parse, and the forms look
+    // like clauses of their own, the error points at the marker they need
+    pub fn params(&self) -> Result<Vec<ParamList>, EvalError> {
+        self.clauses
+            .iter()
+            .map(|(params, _)| {
+                ParamList::parse(params).map_err(|e| {
+                    if !self.unmarked_clauses {
+                        return e;
+                    }
+                    EvalError::String(format!(
+                        "[fn] {}. clauses for several arities need to come after `{}`, \
+                         as in (fn name {} ((params) body ...) ...)",
+                        e, CLAUSES, CLAUSES
+                    ))
+                })
+            })
+            .collect()
+    }
+
+    // the same shape of form, made from new parts
+    pub fn rebuild(&self, head: Value, name: Option<Value>, clauses: Vec<(Value, Value)>) -> Value {
+        let mut form = vec![head];
+        form.extend(name);
+        if let Some(marker) = self.marker {
+            form.push(marker.clone());
+            form.extend(
+                clauses
+                    .into_iter()
+                    .map(|(params, body)| Value::List(vec![params, body].into())),
+            );
+        } else {
+            for (params, body) in clauses {
+                form.extend([params, body]);
+            }
+        }
+        Value::List(form.into())
+    }
+}
+
+// the one name after a `&`, which has to come last
+fn rest_name(
+    name: Option<&Value>,
+    after: Option<&Value>,
+    list: &Value,
+) -> Result<SymbolValue, EvalError> {
+    match (name, after) {
+        (Some(Value::Symbol(name)), None) if !matches!(&**name, REST | OPTIONAL | KEY) => Ok(*name),
+        _ => Err(EvalError::String(format!(
+            "`{}` must be followed by exactly one parameter: {}",
+            REST, list
+        ))),
+    }
+}
+
+// `name` or `(name default)`
+fn with_default(param: &Value) -> Result<(SymbolValue, Option<Value>), EvalError> {
+    match param {
+        Value::Symbol(name) if !is_marker(param) => Ok((*name, None)),
+        Value::List(list) => match &list[..] {
+            [name @ Value::Symbol(s), default] if !is_marker(name) => {
+                Ok((*s, Some(default.clone())))
+            }
+            _ => Err(EvalError::String(format!(
+                "a parameter with a default must look like (name default): {}",
+                param
+            ))),
+        },
+        _ => Err(EvalError::String(format!(
+            "expected a symbol or (name default) in the argument list, got {}",
+            param
+        ))),
+    }
+}
+
+// a keyword param, `name` or `(name default)` to be given as `:name`. the name
+// can also be `(:keyword name)`, for a keyword that isn't the name it's bound to
+fn key_param(param: &Value) -> Result<(SymbolValue, SymbolValue, Option<Value>), EvalError> {
+    let (name, default) = match param.get_list() {
+        Some([name @ Value::List(_)]) => (name, None),
+        Some([name @ Value::List(_), default]) => (name, Some(default.clone())),
+        _ => {
+            let (name, default) = with_default(param)?;
+            return Ok((keyword_for(&name), name, default));
+        }
+    };
+    match name.get_list() {
+        Some([keyword @ Value::Symbol(k), local @ Value::Symbol(name)])
+            if keyword.is_keyword() && !is_marker(local) =>
+        {
+            Ok((*k, *name, default))
+        }
+        _ => Err(EvalError::String(format!(
+            "a keyword parameter's name must be a symbol or (:keyword name): {}",
+            param
+        ))),
+    }
+}
+
+// the keyword that gives a value to the keyword param `name`
+pub fn keyword_for(name: &SymbolValue) -> SymbolValue {
+    SymbolValue::new(&format!(":{}", name))
+}
+
+fn default_value(
+    default: &Option<Value>,
+    bindings: &Bindings,
+    eval_default: &mut impl FnMut(&Value, &Bindings) -> Result<Value, EvalError>,
+) -> Result<Value, EvalError> {
+    match default {
+        Some(default) => eval_default(default, bindings),
+        None => Ok(Value::nil()),
+    }
+}
+
+// whether a symbol in a parameter list is a marker rather than a name
+pub fn is_marker(val: &Value) -> bool {
+    matches!(val.get_symbol(), Some(REST | OPTIONAL | KEY))
+}
@@ -28,13 +28,13 @@ pub fn parse<'a>(tokens: &'a [Token]) -> Result<(Value, &'a [Token]), ParseError
         RemarkEnd => Err(ParseError::Reason("unexpected `*)`".to_string())),
         LeftParen => read_seq(rest),
         RightParen => Err(ParseError::Reason("unexpected `)`".to_string())),
-        Atom(s, is_number) => Ok((parse_atom(&s, is_number), rest)),
+        Atom(s, is_number) => Ok((parse_atom(&s, is_number)?, rest)),
         Quote(inner) => match &**inner {
             LeftParen => {
                 let (val, rest) = read_seq(rest)?;
                 Ok((Value::quoted(val), rest))
             }
-            Atom(s, is_number) => Ok((Value::quoted(parse_atom(&s, is_number)), rest)),
+            Atom(s, is_number) => Ok((Value::quoted(parse_atom(&s, is_number)?), rest)),
             /*..
             Quote(q) => {
                 let (token, _) = parse(&[q])?;
@@ -84,12 +84,15 @@ fn read_remark<'a>(tokens: &'a [Token]) -> Result<(Value, &'a [Token]), ParseErr
     }
 }
 
-fn parse_atom(token: &str, is_number: &bool) -> Value {
+fn parse_atom(token: &str, is_number: &bool) -> Result<Value, ParseError> {
     match is_number {
         true => match token.parse::<i64>() {
-            Ok(v) => Value::Integer(v),
-            Err(e) => panic!("{:?}", e),
+            Ok(v) => Ok(Value::Integer(v)),
+            Err(e) => Err(ParseError::Reason(format!(
+                "bad integer `{}`: {}",
+                token, e
+            ))),
         },
-        false => Value::Symbol(token.to_string().clone()),
+        false => Ok(Value::Symbol(token.into())),
     }
 }
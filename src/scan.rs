@@ -121,16 +121,9 @@ impl<'a> Scanner<'a> {
                     self.cursor += ch.len_utf8();
                     break 'token Some(Token::new(RightParen, short_span));
                 }
-                '*' => {
-                    self.cursor += ch.len_utf8();
-                    let ch = char_at_index(self.source, self.cursor)?;
-                    match ch {
-                        ')' => {
-                            self.cursor += ch.len_utf8();
-                            break 'token Some(Token::new(RemarkEnd, short_span));
-                        }
-                        _ => panic!(),
-                    }
+                '*' if char_at_index(self.source, self.cursor + 1) == Some(')') => {
+                    self.cursor += 2;
+                    break 'token Some(Token::new(RemarkEnd, short_span));
                 }
                 '\'' => {
                     let first = self.cursor;
@@ -145,35 +138,23 @@ impl<'a> Scanner<'a> {
                     self.cursor += ch.len_utf8();
                     continue;
                 }
-                ch if ch.is_digit(36) => {
-                    let is_number = ch.is_digit(10);
+                // anything else runs until the next delimiter, so names like
+                // `macroexpand-1` or `<=` are single atoms
+                _ => {
                     let first = self.cursor;
-                    let mut ch = ch;
                     let mut atom = String::new();
-                    while ch.is_digit(36) {
+                    while let Some(ch) = char_at_index(self.source, self.cursor) {
+                        if ch.is_whitespace() || ch == '(' || ch == ')' {
+                            break;
+                        }
                         atom.push(ch);
                         self.cursor += ch.len_utf8();
-                        match char_at_index(self.source, self.cursor) {
-                            Some(ch_in) => {
-                                ch = ch_in;
-                                if ch.is_whitespace() || ch == '(' || ch == ')' {
-                                    let token =
-                                        Token::new(Atom(atom, is_number), first..self.cursor);
-                                    break 'token Some(token);
-                                }
-                            }
-                            None => {
-                                let token = Token::new(Atom(atom, is_number), first..self.cursor);
-                                break 'token Some(token);
-                            }
-                        }
                     }
+                    let digits = atom.strip_prefix('-').unwrap_or(&atom);
+                    let is_number =
+                        !digits.is_empty() && digits.bytes().all(|b| b.is_ascii_digit());
+                    break 'token Some(Token::new(Atom(atom, is_number), first..self.cursor));
                 }
-                _ => break 'token None,
-            }
-            match char_at_index(self.source, self.cursor) {
-                Some(ch) => self.cursor += ch.len_utf8(),
-                None => return None,
             }
         };
         return token;
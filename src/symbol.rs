@@ -0,0 +1,164 @@
+use std::collections::HashSet;
+use std::hash::{Hash, Hasher};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{OnceLock, RwLock};
+
+// Interned symbols
+//
+// every name is stored once, in a table shared by the whole process, and a
+// symbol just points at it. two symbols are equal, and hash the same, when they
+// point at the same name, so neither has to look at the characters.
+//
+// names are never freed: the table is a leak that lives as long as the process
+// and is shared by every interpreter in it. a budget is charged for the names
+// in data it reads (see `Value::heap_size`), but what one interpreter interns
+// stays for all the others
+//
+// each symbol made from a name also gets a site of its own, which its copies
+// share. macro hygiene uses it to tell the symbols a caller wrote apart from
+// the same names written in the macro. a fresh symbol from `gensym` is told
+// apart from every other one by its site alone, so making one doesn't add a
+// name to the table
+#[derive(Clone, Copy)]
+pub struct SymbolValue {
+    name: &'static str,
+    site: u64,
+    fresh: bool,
+}
+
+static SITES: AtomicU64 = AtomicU64::new(0);
+
+// names are only looked up far more often than they're added, so lookups
+// share the lock
+fn table() -> &'static RwLock<HashSet<&'static str>> {
+    static TABLE: OnceLock<RwLock<HashSet<&'static str>>> = OnceLock::new();
+    TABLE.get_or_init(Default::default)
+}
+
+fn interned(name: &str) -> Option<&'static str> {
+    let table = table().read().unwrap_or_else(|e| e.into_inner());
+    table.get(name).copied()
+}
+
+fn intern(name: &str) -> &'static str {
+    if let Some(interned) = interned(name) {
+        return interned;
+    }
+    let mut table = table().write().unwrap_or_else(|e| e.into_inner());
+    match table.get(name) {
+        Some(interned) => interned,
+        None => {
+            let interned: &'static str = Box::leak(name.into());
+            table.insert(interned);
+            interned
+        }
+    }
+}
+
+impl SymbolValue {
+    pub fn new(name: &str) -> SymbolValue {
+        SymbolValue::at_new_site(intern(name), false)
+    }
+
+    // a symbol equal to no other, printed as the prefix followed by a number.
+    // only `prefix%` is interned, once for all the symbols that share it
+    pub fn fresh(prefix: &str) -> SymbolValue {
+        SymbolValue::at_new_site(intern(&format!("{}%", prefix)), true)
+    }
+
+    // the symbol for a name that has already been interned, without interning
+    // it if it hasn't. nothing can be bound to a name that was never interned
+    pub fn find(name: &str) -> Option<SymbolValue> {
+        Some(SymbolValue::at_new_site(interned(name)?, false))
+    }
+
+    fn at_new_site(name: &'static str, fresh: bool) -> SymbolValue {
+        SymbolValue {
+            name,
+            site: SITES.fetch_add(1, Ordering::Relaxed),
+            fresh,
+        }
+    }
+
+    // the interned name, which for a fresh symbol is only its `prefix%`, so
+    // symbols are compared themselves rather than by this
+    pub fn as_str(&self) -> &'static str {
+        self.name
+    }
+
+    pub fn site(&self) -> u64 {
+        self.site
+    }
+}
+
+impl PartialEq for SymbolValue {
+    fn eq(&self, other: &SymbolValue) -> bool {
+        std::ptr::eq(self.name, other.name)
+            && self.fresh == other.fresh
+            && (!self.fresh || self.site == other.site)
+    }
+}
+
+impl Eq for SymbolValue {}
+
+impl Hash for SymbolValue {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.name.as_ptr().hash(state);
+        if self.fresh {
+            self.site.hash(state)
+        }
+    }
+}
+
+// by name, with fresh symbols after the name they were made from and in the
+// order they were made
+impl Ord for SymbolValue {
+    fn cmp(&self, other: &SymbolValue) -> std::cmp::Ordering {
+        let site = |s: &SymbolValue| s.fresh.then_some(s.site);
+        self.name
+            .cmp(other.name)
+            .then_with(|| site(self).cmp(&site(other)))
+    }
+}
+
+impl PartialOrd for SymbolValue {
+    fn partial_cmp(&self, other: &SymbolValue) -> Option<std::cmp::Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl core::ops::Deref for SymbolValue {
+    type Target = str;
+
+    fn deref(&self) -> &str {
+        self.name
+    }
+}
+
+impl core::fmt::Display for SymbolValue {
+    fn fmt(&self, f: &mut core::fmt::Formatter) -> core::fmt::Result {
+        f.write_str(self.name)?;
+        if self.fresh {
+            write!(f, "{}", self.site)?;
+        }
+        Ok(())
+    }
+}
+
+impl core::fmt::Debug for SymbolValue {
+    fn fmt(&self, f: &mut core::fmt::Formatter) -> core::fmt::Result {
+        core::fmt::Display::fmt(self, f)
+    }
+}
+
+impl From<&str> for SymbolValue {
+    fn from(name: &str) -> SymbolValue {
+        SymbolValue::new(name)
+    }
+}
+
+impl From<String> for SymbolValue {
+    fn from(name: String) -> SymbolValue {
+        SymbolValue::new(&name)
+    }
+}
@@ -36,11 +36,113 @@ fn safe_unsafe_call() {
 #[test]
 
 fn lua_core() {
-    let env = LispEnv::default();
+    use capability::{Capabilities, Capability};
+
+    let base_env = LispEnv::default();
+
+    let env = base_env.new_capable_env(Capabilities::none().allow(Capability::Process));
 
     parse_eval("(unsafe (lua 'core))", &env).unwrap();
 }
 
+#[test]
+fn capabilities() {
+    use capability::{Capabilities, Capability};
+    use eval::EvalError;
+
+    let base_env = LispEnv::default();
+
+    let denied = |env: &LispEnv, src: &str, capability: Capability| {
+        let err = env.eval(&parse_string(src).unwrap()).unwrap_err();
+        assert!(
+            matches!(err, EvalError::MissingCapability(c, _) if c == capability),
+            "{}",
+            err
+        );
+    };
+
+    denied(&base_env, "(unsafe (readf 'std))", Capability::FsRead);
+    denied(&base_env, "(unsafe (lua 'core))", Capability::Process);
+    denied(&base_env, "(unsafe (readline 'x))", Capability::Stdin);
+
+    let env = base_env.new_capable_env(Capabilities::none().allow_read("src/"));
+    denied(&env, "(unsafe (readf 'std))", Capability::FsRead);
+    // a denial inside `include` is an error, not a panic
+    assert!(parse_eval("(unsafe (include 'std 1))", &env).is_err());
+
+    let env = base_env.new_capable_env(Capabilities::none().allow_read("./lisb"));
+    assert!(parse_eval("(unsafe (readf 'std))", &env).unwrap().is_list());
+    denied(&env, "(unsafe (lua 'core))", Capability::Process);
+
+    let read = env.capabilities();
+    assert!(read.check_read("lisb/std.l").is_ok());
+    assert!(read.check_read("src/../lisb/std.l").is_ok());
+    assert!(read.check_read("lisb/../src/main.rs").is_err());
+    assert!(read.check_read("../lisb/std.l").is_err());
+    assert!(read.check_read("lisbon/std.l").is_err());
+    assert!(read.check_write("lisb/std.l").is_err());
+}
+
+#[test]
+fn deferred_effects() {
+    use capability::Capabilities;
+    use eval::run_effects;
+
+    let base_env = LispEnv::default();
+
+    let env = base_env.new_capable_env(Capabilities::none().allow_read("lisb"));
+
+    // calling an unsafe function outside of `unsafe` only describes the call
+    let call = parse_eval("(spookyAdd 10 (add 30 2))", &env).unwrap();
+    assert!(matches!(call, LispValue::UnsafeCall(_)));
+    assert_eq!(call.to_string(), "[UnsafeCall spookyAdd 10 32]");
+    assert_eq!(run_effects(&env, call).unwrap(), LispValue::Integer(42));
+
+    // a described call can be passed around and run later
+    let src = "(let '((action (readf 'std))) (unsafe action))";
+    assert!(parse_eval(src, &env).unwrap().is_list());
+
+    let src = "(let '((load (fn (name) (readf name)))) (unsafe (load 'std)))";
+    assert!(parse_eval(src, &env).unwrap().is_list());
+
+    // capabilities are checked when the effect is run, not when it is described
+    let call = parse_eval("(readf 'std)", &base_env).unwrap();
+    assert!(matches!(call, LispValue::UnsafeCall(_)));
+    assert!(run_effects(&base_env, call.clone()).is_err());
+    assert!(run_effects(&env, call).unwrap().is_list());
+
+    // reduction describes effects instead of running them
+    let reduced = env
+        .reduce(&parse_string("(spookyAdd 1 2)").unwrap())
+        .unwrap();
+    assert!(matches!(reduced, LispValue::UnsafeCall(_)));
+    assert_eq!(run_effects(&env, reduced).unwrap(), LispValue::Integer(3));
+
+    // the arguments of an effect that reads them as written are kept as forms
+    let src = "(let '((action (include 'std (fib 5)))) (unsafe action))";
+    assert_eq!(parse_eval(src, &env).unwrap(), LispValue::Integer(5));
+    let call = parse_eval("(include 'std (fib 5))", &env).unwrap();
+    assert_eq!(call.to_string(), "[UnsafeCall include (quote std) (fib 5)]");
+
+    // effects nested inside `unsafe` are run where they are
+    let src = "(unsafe (let ((x 1)) (list (spookyAdd x 1) 2)))";
+    assert_eq!(
+        parse_eval(src, &env).unwrap(),
+        parse_string("(2 2)").unwrap()
+    );
+    let src = "(unsafe (let ((x 1)) (add (spookyAdd x 1) 2)))";
+    assert_eq!(parse_eval(src, &env).unwrap(), LispValue::Integer(4));
+
+    // and one that was described elsewhere isn't handed back unrun
+    let src = "(let '((action (spookyAdd 1 1))) (unsafe (list action 2)))";
+    let err = parse_eval(src, &env).unwrap_err().to_string();
+    assert!(
+        err.contains("can't run an effect inside a value"),
+        "{}",
+        err
+    );
+}
+
 #[test]
 fn std_test() {
     let base_env = LispEnv::default();
@@ -95,6 +197,1067 @@ fn std_test() {
     */
 }
 
+#[test]
+fn runtime_macros() {
+    let base_env = LispEnv::default();
+
+    let env = get_std(&base_env).unwrap();
+
+    use LispValue::*;
+
+    let tests = &[
+        ("((macro (a b) b) (undefined stuff) 42)", Integer(42)),
+        ("((macro (a) (list 'add a a)) (add 1 2))", Integer(6)),
+        (
+            "(let '((m (macro (x) (list 'quote x)))) (m (not evaluated)))",
+            parse_eval("'(not evaluated)", &env).unwrap(),
+        ),
+        ("(cond ((lt 2 1) 1) ((eq 1 1) 2) (true 3))", Integer(2)),
+        ("(cond ((lt 2 1) 1))", List(vec![].into())),
+        // the expansion is evaluated where the macro is called
+        (
+            "(let '((x 5)) ((macro (a) (list 'add a 'x)) 1))",
+            Integer(6),
+        ),
+    ];
+
+    for (src, result) in tests {
+        let parsed = parse_string(src).unwrap();
+        assert_eq!(env.eval(&parsed).unwrap(), *result, "{}", src);
+        assert_eq!(
+            env.eval(&env.reduce(&parsed).unwrap()).unwrap(),
+            *result,
+            "{}",
+            src
+        );
+    }
+}
+
+#[test]
+fn macro_expansion_pass() {
+    let base_env = LispEnv::default();
+
+    let std_env = get_std(&base_env).unwrap();
+
+    let env = std_env
+        .new_inner_from_pairs(
+            &parse_string(
+                "((dbl (macro (a) (list 'add a a))) (twice (macro (a) (list 'dbl (list 'dbl a)))))",
+            )
+            .unwrap(),
+        )
+        .unwrap();
+
+    let tests = &[
+        ("(dbl 3)", "(add 3 3)"),
+        ("(twice 3)", "(add (add 3 3) (add 3 3))"),
+        (
+            "(sub 10 (dbl (dbl 1)))",
+            "(sub 10 (add (add 1 1) (add 1 1)))",
+        ),
+        ("'(dbl 3)", "'(dbl 3)"),
+        ("(list 1 (quote (twice x)))", "(list 1 (quote (twice x)))"),
+        // names bound locally hide macros
+        ("((fn (dbl) (dbl 1)) id)", "((fn (dbl) (dbl 1)) id)"),
+        ("(let '((dbl id)) (dbl 1))", "(let '((dbl id)) (dbl 1))"),
+        // a macro that writes a macro
+        (
+            "(((macro () (list 'macro '(a) '(list 'mul a a)))) 5)",
+            "(mul 5 5)",
+        ),
+    ];
+
+    for (src, expanded) in tests {
+        let parsed = parse_string(src).unwrap();
+        let passed = env.macro_eval(&parsed).unwrap();
+        assert_eq!(passed, parse_string(expanded).unwrap(), "{}", src);
+        assert_eq!(
+            env.eval(&passed).unwrap(),
+            env.eval(&parsed).unwrap(),
+            "{}",
+            src
+        );
+    }
+
+    // macros from std are visible to the pass
+    let passed = env
+        .macro_eval(&parse_string("(case 3 ((1 2) 1) (else 2))").unwrap())
+        .unwrap();
+    assert_eq!(
+        passed.get_list().unwrap()[0],
+        LispValue::Symbol("let".into())
+    );
+    assert_eq!(env.eval(&passed).unwrap(), LispValue::Integer(2));
+
+    let result = parse_macro_pass_and_eval("(twice 4)", &env).unwrap();
+    assert_eq!(result, LispValue::Integer(16));
+}
+
+#[test]
+fn macroexpand_builtins() {
+    let base_env = LispEnv::default();
+
+    let env = get_std(&base_env).unwrap();
+
+    let env = env
+        .new_inner_from_pairs(
+            &parse_string(
+                "((dbl (macro (a) (list 'add a a))) (quad (macro (a) (list 'dbl (list 'dbl a)))))",
+            )
+            .unwrap(),
+        )
+        .unwrap();
+
+    let tests = &[
+        ("(macroexpand-1 '(quad 3))", "((dbl (dbl 3)) [true])"),
+        ("(macroexpand-1 '(add 1 2))", "((add 1 2) [false])"),
+        ("(macroexpand-1 5)", "(5 [false])"),
+        ("(macroexpand-all '(quad 3))", "(add (add 3 3) (add 3 3))"),
+        (
+            "(macroexpand-all '(sub (dbl 1) '(dbl 2)))",
+            "(sub (add 1 1) (quote (dbl 2)))",
+        ),
+    ];
+
+    for (src, expected) in tests {
+        assert_eq!(parse_eval(src, &env).unwrap().to_string(), *expected);
+    }
+
+    let long = parse_eval("(macroexpand-all '(quad 3))", &env).unwrap();
+    assert_eq!(long.pretty(80), long.to_string());
+    assert_eq!(long.pretty(20), "(add\n  (add 3 3)\n  (add 3 3))");
+}
+
+#[test]
+fn macro_hygiene() {
+    let base_env = LispEnv::default();
+
+    let std_env = get_std(&base_env).unwrap();
+
+    // `swap` calls a function with its two arguments the other way around, and
+    // `or2` only evaluates its first argument once. both need a temporary
+    let env = std_env
+        .new_inner_from_pairs(
+            &parse_string(
+                "(
+                    (swap (macro (f a b)
+                        (list 'let (list 'quote (list (list 'tmp a))) (list f b 'tmp))))
+                    (or2 (macro (a b)
+                        (list 'let (list 'quote (list (list 'tmp a)))
+                            (list 'if '(truthyP tmp) 'tmp b))))
+                    (twice (macro (f x)
+                        (list (list 'fn '(tmp) (list f (list f 'tmp))) x)))
+                )",
+            )
+            .unwrap(),
+        )
+        .unwrap();
+
+    use LispValue::*;
+
+    let tests = &[
+        ("(swap sub 5 30)", Integer(25)),
+        // without renaming, the `tmp` passed in would be the macro's own `tmp`
+        ("(let '((tmp 30)) (swap sub 5 tmp))", Integer(25)),
+        ("(let '((tmp 7)) (or2 false tmp))", Integer(7)),
+        ("(let '((tmp 7)) (or2 tmp 3))", Integer(7)),
+        (
+            "(let '((tmp 2)) (twice (fn (x) (add x tmp)) 1))",
+            Integer(5),
+        ),
+        // binders passed in by the caller are left alone
+        ("(or2 false (let '((tmp 4)) tmp))", Integer(4)),
+        ("(twice (fn (tmp) (add tmp tmp)) 3)", Integer(12)),
+    ];
+
+    for (src, result) in tests {
+        let parsed = parse_string(src).unwrap();
+        assert_eq!(env.eval(&parsed).unwrap(), *result, "{}", src);
+        let passed = env.macro_eval(&parsed).unwrap();
+        assert_eq!(env.eval(&passed).unwrap(), *result, "{}", src);
+    }
+
+    // the macro's own `tmp` gets a name of its own
+    let expansion = parse_eval("(car (macroexpand-1 '(swap sub 5 tmp)))", &env).unwrap();
+    let name = expansion.get_list().unwrap()[1].get_list().unwrap()[1]
+        .get_list()
+        .unwrap()[0]
+        .get_list()
+        .unwrap()[0]
+        .clone();
+    assert_ne!(name, Symbol("tmp".into()));
+    assert!(name.get_symbol().unwrap().starts_with("tmp"));
+
+    let a = parse_eval("(gensym)", &env).unwrap();
+    let b = parse_eval("(gensym)", &env).unwrap();
+    assert_ne!(a, b);
+    assert!(parse_eval("(gensym 'tmp)", &env)
+        .unwrap()
+        .get_symbol()
+        .unwrap()
+        .starts_with("tmp"));
+}
+
+#[test]
+fn syntax_rules() {
+    let base_env = LispEnv::default();
+
+    let std_env = get_std(&base_env).unwrap();
+
+    let env = std_env
+        .new_inner_from_pairs(
+            &parse_string(
+                "(
+                    (myOr (syntax-rules ()
+                        ((_) false)
+                        ((_ e) e)
+                        ((_ e rest ...) (let '((t e)) (if (truthyP t) t (myOr rest ...))))))
+                    (pairs (syntax-rules ()
+                        ((_ (a b) ...) '((a ...) (b ...)))))
+                    (dots (syntax-rules ()
+                        ((_ x) '(x (... ...)))))
+                    (for (syntax-rules (in)
+                        ((_ x in l body) (map (fn (x) body) l))))
+                    (map (fn (f l) (if (truthyP l) (cons (f (car l)) (map f (cdr l))) ())))
+                )",
+            )
+            .unwrap(),
+        )
+        .unwrap();
+
+    use LispValue::*;
+
+    let tests = &[
+        ("(when (lt 1 2) 5)", Integer(5)),
+        ("(when (lt 2 1) 5)", List(vec![].into())),
+        ("(unless (lt 2 1) 5)", Integer(5)),
+        ("(cond ((lt 2 1) 1) ((eq 1 1) 2) (else 3))", Integer(2)),
+        ("(cond ((lt 2 1) 1) (else 3))", Integer(3)),
+        (
+            "(let* ((a 1) (b (add a 1)) (c (add b a))) (list a b c))",
+            parse_eval("'(1 2 3)", &env).unwrap(),
+        ),
+        (
+            "(case (add 1 2) ((1 2) 'low) ((3 4) 'mid) (else 'high))",
+            Symbol("mid".into()),
+        ),
+        (
+            "(case 9 ((1 2) 'low) ((3 4) 'mid) (else 'high))",
+            Symbol("high".into()),
+        ),
+        ("(myOr false () 4 5)", Integer(4)),
+        ("(myOr)", Bool(false)),
+        // the `t` the macro binds doesn't capture the caller's `t`
+        ("(let '((t 7)) (myOr false t))", Integer(7)),
+        (
+            "(pairs (1 2) (3 4) (5 6))",
+            parse_eval("'((1 3 5) (2 4 6))", &env).unwrap(),
+        ),
+        ("(pairs)", parse_eval("'(() ())", &env).unwrap()),
+        ("(dots 1)", parse_eval("'(1 ...)", &env).unwrap()),
+        (
+            "(for x in '(1 2 3) (add x x))",
+            parse_eval("'(2 4 6)", &env).unwrap(),
+        ),
+    ];
+
+    for (src, result) in tests {
+        let parsed = parse_string(src).unwrap();
+        assert_eq!(env.eval(&parsed).unwrap(), *result, "{}", src);
+        let passed = env.macro_eval(&parsed).unwrap();
+        assert_eq!(env.eval(&passed).unwrap(), *result, "{}", src);
+    }
+
+    // literals only match themselves
+    assert!(parse_eval("(for x on '(1 2 3) x)", &env).is_err());
+    assert!(parse_eval("(syntax-rules (1) ((_) 1))", &env).is_err());
+}
+
+#[test]
+fn rest_params() {
+    let base_env = LispEnv::default();
+
+    let std_env = get_std(&base_env).unwrap();
+
+    let env = std_env
+        .new_inner_from_pairs(
+            &parse_string(
+                "(
+                    (sum (fn (& xs) (if (truthyP xs) (add (car xs) (apply sum (cdr xs))) 0)))
+                    (tail (fn (a & r) r))
+                    (quoteAll (macro (& forms) (list 'quote forms)))
+                    (twice (macro (f & args) (list 'list (cons f args) (cons f args))))
+                )",
+            )
+            .unwrap(),
+        )
+        .unwrap();
+
+    let tests = &[
+        ("(tail 1 2 3)", "(2 3)"),
+        ("(tail 1)", "()"),
+        ("((fn (& r) r))", "()"),
+        ("(sum 1 2 3 4)", "10"),
+        ("(quoteAll a (b c) 1)", "(a (b c) 1)"),
+        ("(twice add 1 2)", "(3 3)"),
+        ("(apply add '(1 2 3))", "6"),
+        ("(apply tail '(1 2))", "(2)"),
+        ("(apply list (list 'a (add 1 1)))", "(a 2)"),
+        ("(apply quoteAll '(x y))", "(x y)"),
+        // the params are bound closer than anything the closure captured
+        ("(let '((x 1)) ((fn (x) x) 5))", "5"),
+    ];
+
+    for (src, result) in tests {
+        let parsed = parse_string(src).unwrap();
+        assert_eq!(env.eval(&parsed).unwrap().to_string(), *result, "{}", src);
+        let passed = env.macro_eval(&parsed).unwrap();
+        assert_eq!(env.eval(&passed).unwrap().to_string(), *result, "{}", src);
+    }
+
+    assert!(parse_eval("(tail)", &env).is_err());
+    assert!(parse_eval("((fn (a b) a) 1)", &env).is_err());
+    assert!(parse_eval("(fn (a &) a)", &env).is_err());
+    assert!(parse_eval("(fn (& a b) a)", &env).is_err());
+    assert!(parse_eval("(apply add 1)", &env).is_err());
+}
+
+#[test]
+fn optional_and_keyword_params() {
+    let base_env = LispEnv::default();
+
+    let std_env = get_std(&base_env).unwrap();
+
+    let env = std_env
+        .new_inner_from_pairs(
+            &parse_string(
+                "(
+                    (box (fn (w &optional (h w) d) (list w h d)))
+                    (scaled (let '((scale 3)) (fn (x &optional (by scale)) (mul x by))))
+                    (rect (fn (&key (width 10) (height (add width 1))) (list width height)))
+                    (tagged (fn (tag &optional n &key label) (list tag n label)))
+                    (wrap (macro (body &key (with 'list)) (list with body)))
+                    (sized (fn (&key ((:size s) 2) ((:name n))) (list s n)))
+                    (keyed (syntax-rules ()
+                        ((_ body) (fn (&key (width 10) height) (list width height body)))))
+                )",
+            )
+            .unwrap(),
+        )
+        .unwrap();
+
+    let tests = &[
+        ("(box 1)", "(1 1 ())"),
+        ("(box 1 2)", "(1 2 ())"),
+        ("(box 1 2 3)", "(1 2 3)"),
+        ("(scaled 2)", "6"),
+        ("(scaled 2 5)", "10"),
+        // the default is evaluated in the scope the fn was made in
+        ("(let '((scale 100)) (scaled 2))", "6"),
+        ("(rect)", "(10 11)"),
+        ("(rect :height 2)", "(10 2)"),
+        ("(rect :height 2 :width 4)", "(4 2)"),
+        ("(rect :width 4)", "(4 5)"),
+        ("(tagged 'a)", "(a () ())"),
+        ("(tagged 'a 1 :label 'x)", "(a 1 x)"),
+        ("(wrap 1)", "(1)"),
+        ("(wrap (add 1 2) :with quote)", "(add 1 2)"),
+        (":width", ":width"),
+        ("(base 570 16)", "(2 3 10)"),
+        ("(base 570 16 :digits 5)", "(0 0 2 3 10)"),
+        ("(base 5 2)", "(1 0 1)"),
+        ("(base 42)", "(4 2)"),
+        ("(base 0 :digits 3)", "(0 0 0)"),
+        ("(sized)", "(2 ())"),
+        ("(sized :size 5 :name 'x)", "(5 x)"),
+    ];
+
+    for (src, result) in tests {
+        let parsed = parse_string(src).unwrap();
+        assert_eq!(env.eval(&parsed).unwrap().to_string(), *result, "{}", src);
+        let passed = env.macro_eval(&parsed).unwrap();
+        assert_eq!(env.eval(&passed).unwrap().to_string(), *result, "{}", src);
+    }
+
+    let errors = &[
+        "(box)",
+        "(box 1 2 3 4)",
+        "(rect :depth 1)",
+        "(rect :width)",
+        "(rect 1 2)",
+        "(rect :width 1 :width 2)",
+        "(fn (&key a & r) a)",
+        "(fn (&key a &optional b) a)",
+        "(fn (&optional (a)) a)",
+        "(sized :s 1)",
+        "(fn (&key ((size s) 1)) s)",
+    ];
+    for src in errors {
+        assert!(parse_eval(src, &env).is_err(), "{}", src);
+    }
+
+    // the keyword params of a fn a macro makes are renamed like any other
+    // name it introduces, but are still given with the keyword they were
+    // written with
+    let tests = &[
+        ("((keyed 1))", "(10 () 1)"),
+        ("((keyed 1) :width 3 :height 4)", "(3 4 1)"),
+        ("(let '((width 7)) ((keyed width) :width 3))", "(3 () 7)"),
+    ];
+    for (src, result) in tests {
+        let value = parse_macro_pass_and_eval(src, &env).unwrap();
+        assert_eq!(value.to_string(), *result, "{}", src);
+    }
+}
+
+#[test]
+fn destructuring() {
+    let base_env = LispEnv::default();
+
+    let std_env = get_std(&base_env).unwrap();
+
+    let env = std_env
+        .new_inner_from_pairs(
+            &parse_string(
+                "(
+                    (((one two) & more) '((1 2) 3 4))
+                    (dot (fn ((x1 y1) (x2 y2)) (add (mul x1 x2) (mul y1 y2))))
+                    (swap (macro ((a b)) (list 'quote (list b a))))
+                    (point (fn ((x y) &optional (scale 1)) (list (mul x scale) (mul y scale))))
+                )",
+            )
+            .unwrap(),
+        )
+        .unwrap();
+
+    let tests = &[
+        ("(list one two more)", "(1 2 (3 4))"),
+        ("(let '(((a b) '(1 2))) (add a b))", "3"),
+        (
+            "(let '(((a (b c)) '(1 (2 3))) (d 4)) (list a b c d))",
+            "(1 2 3 4)",
+        ),
+        ("(let '(((h & t) '(1 2 3))) (list h t))", "(1 (2 3))"),
+        ("(let '((() ())) 1)", "1"),
+        ("(dot '(1 2) '(3 4))", "11"),
+        ("((fn ((a & r)) r) '(1 2 3))", "(2 3)"),
+        ("(swap (x y))", "(y x)"),
+        ("(point '(1 2))", "(1 2)"),
+        ("(point '(1 2) 3)", "(3 6)"),
+    ];
+
+    for (src, result) in tests {
+        let parsed = parse_string(src).unwrap();
+        assert_eq!(env.eval(&parsed).unwrap().to_string(), *result, "{}", src);
+        let passed = env.macro_eval(&parsed).unwrap();
+        assert_eq!(env.eval(&passed).unwrap().to_string(), *result, "{}", src);
+    }
+
+    // the error says which pattern didn't fit
+    let err = parse_eval("(let '(((a (b c)) '(1 (2)))) a)", &env).unwrap_err();
+    assert!(err.to_string().contains("(b c)"), "{}", err);
+    let err = parse_eval("(dot '(1 2) 3)", &env).unwrap_err();
+    assert!(err.to_string().contains("(x2 y2)"), "{}", err);
+    assert!(parse_eval("(let '(((a & b c) '(1 2 3))) a)", &env).is_err());
+    assert!(parse_eval("(fn ((a 1)) a)", &env).is_err());
+}
+
+#[test]
+fn match_expression() {
+    let base_env = LispEnv::default();
+
+    let std_env = get_std(&base_env).unwrap();
+
+    let env = std_env
+        .new_inner_from_pairs(
+            &parse_string(
+                "(
+                    (describe (fn (x) (match x
+                        (0 'zero)
+                        (true 'yes)
+                        (() 'empty)
+                        ('hello 'greeting)
+                        ((? atom (? (fn (n) (lt n 0)) n)) (list 'negative n))
+                        ((? atom) 'atom)
+                        ((a a) (list 'pair-of a))
+                        ((_ (b & _) & more) (list b more))
+                        (_ 'other))))
+                    (len (fn (l) (match l (() 0) ((_ & t) (add 1 (len t))))))
+                    (myLet (macro (name value body)
+                        (list 'match value (list 'tmp (list 'match name (list 'tmp body))))))
+                )",
+            )
+            .unwrap(),
+        )
+        .unwrap();
+
+    let tests = &[
+        ("(describe 0)", "zero"),
+        ("(describe true)", "yes"),
+        ("(describe ())", "empty"),
+        ("(describe 'hello)", "greeting"),
+        ("(describe -5)", "(negative -5)"),
+        ("(describe 7)", "atom"),
+        ("(describe '(1 1))", "(pair-of 1)"),
+        ("(describe '(1 2))", "other"),
+        ("(describe '(1 (2 3) 4 5))", "(2 (4 5))"),
+        ("(describe '((1)))", "other"),
+        ("(len '(1 2 3))", "3"),
+        ("(match :a (:b 1) (:a 2))", "2"),
+        ("(match '(1 2) ((x y) (add x y)))", "3"),
+        // the pattern's names are only bound in its own clause
+        ("(let '((x 10)) (match 1 (2 x) (y x)))", "10"),
+        // the macro's `tmp` doesn't capture the caller's
+        ("(let '((tmp 5)) (myLet 1 2 tmp))", "5"),
+    ];
+
+    for (src, result) in tests {
+        let parsed = parse_string(src).unwrap();
+        assert_eq!(env.eval(&parsed).unwrap().to_string(), *result, "{}", src);
+        let passed = env.macro_eval(&parsed).unwrap();
+        assert_eq!(env.eval(&passed).unwrap().to_string(), *result, "{}", src);
+        let reduced = env.reduce(&parsed).unwrap();
+        assert_eq!(env.eval(&reduced).unwrap().to_string(), *result, "{}", src);
+    }
+
+    let err = parse_eval("(match '(1 2) ((a) a) (3 3))", &env).unwrap_err();
+    assert_eq!(
+        err.to_string(),
+        "[match] no pattern matches (1 2), tried: (a) 3"
+    );
+    assert!(parse_eval("(match 1 (& 1))", &env).is_err());
+    // guards use the same truthiness as every other conditional
+    assert_eq!(
+        parse_eval("(match 3 ((? (fn (x) x) y) y) (_ 0))", &env).unwrap(),
+        LispValue::Integer(3)
+    );
+    assert_eq!(
+        parse_eval("(match () ((? id) 1) (_ 0))", &env).unwrap(),
+        LispValue::Integer(0)
+    );
+    assert!(parse_eval("(match 1 (1))", &env).is_err());
+}
+
+#[test]
+fn named_and_multi_arity_lambdas() {
+    let base_env = LispEnv::default();
+
+    let std_env = get_std(&base_env).unwrap();
+
+    let env = std_env
+        .new_inner_from_pairs(
+            &parse_string(
+                "(
+                    (total (fn sum :clauses
+                        ((l) (sum l 0))
+                        ((l acc) (if (truthyP l) (sum (cdr l) (add acc (car l))) acc))))
+                    (loopy (macro (x)
+                        (list (list 'fn 'go '(n) (list 'if '(eq n 0) x '(go (sub n 1)))) 2)))
+                )",
+            )
+            .unwrap(),
+        )
+        .unwrap();
+
+    let tests = &[
+        // nothing outside the lambda is called `count`
+        (
+            "((fn count (n) (if (eq n 0) 'done (count (sub n 1)))) 3)",
+            "done",
+        ),
+        ("(total '(1 2 3))", "6"),
+        ("(total '(1 2) 10)", "13"),
+        ("((fn :clauses ((a) a) ((a b) b)) 1 2)", "2"),
+        ("((fn :clauses ((a) 'one) ((a & r) 'many)) 1 2 3)", "many"),
+        ("((fn :clauses ((a) 'one) ((a & r) 'many)) 1)", "one"),
+        // without `:clauses`, forms that look like clauses are still patterns
+        (
+            "(let ((f (fn (x) (fn (y) (add x y))))) ((fn ((a b) (c d)) ((f a) d)) '(1 2) '(3 4)))",
+            "5",
+        ),
+        // a param of the same name hides the lambda
+        ("((fn f (f) f) 5)", "5"),
+        ("(div 17 5)", "3"),
+        ("(mul 6 7)", "42"),
+        ("(pow 3 2)", "9"),
+        // the macro's `go` doesn't capture the caller's
+        ("(let '((go 1)) (loopy go))", "1"),
+    ];
+
+    for (src, result) in tests {
+        let parsed = parse_string(src).unwrap();
+        assert_eq!(env.eval(&parsed).unwrap().to_string(), *result, "{}", src);
+        let passed = env.macro_eval(&parsed).unwrap();
+        assert_eq!(env.eval(&passed).unwrap().to_string(), *result, "{}", src);
+        let reduced = env.reduce(&parsed).unwrap();
+        assert_eq!(env.eval(&reduced).unwrap().to_string(), *result, "{}", src);
+    }
+
+    let err = parse_eval("(total)", &env).unwrap_err();
+    assert_eq!(err.to_string(), "no clause of sum takes 0 arguments");
+    assert!(parse_eval("(fn f)", &env).is_err());
+    assert!(parse_eval("(fn :clauses ((a) a) ((1) 1))", &env).is_err());
+    assert!(parse_eval("(fn :clauses)", &env).is_err());
+    assert!(parse_eval("(fn :clauses (a) a)", &env).is_err());
+
+    // clauses written without the marker are pointed at it
+    let src = "(fn div ((d v) (div d v 0)) ((d v i) (add d v i)))";
+    let err = parse_eval(src, &env).unwrap_err().to_string();
+    assert!(err.contains("need to come after `:clauses`"), "{}", err);
+    let err = parse_eval("(fn (1) 1)", &env).unwrap_err().to_string();
+    assert!(!err.contains(":clauses"), "{}", err);
+}
+
+#[test]
+fn native_arithmetic() {
+    let base_env = LispEnv::default();
+
+    let env = get_std(&base_env).unwrap();
+
+    let tests = &[
+        ("(mul 100000 3)", "300000"),
+        ("(mul 2 3 4)", "24"),
+        ("(mul)", "1"),
+        ("(div 100 30)", "3"),
+        ("(div 100 5 2)", "10"),
+        ("(div -7 2)", "-3"),
+        ("(rem 7 3)", "1"),
+        ("(rem -7 3)", "-1"),
+        ("(mod -7 3)", "2"),
+        ("(mod 7 -3)", "-2"),
+        ("(mod 6 -3)", "0"),
+        ("(neg 5)", "-5"),
+        ("(abs -5)", "5"),
+        ("(min 3 1 2)", "1"),
+        ("(max 3 1 2)", "3"),
+        ("(base 255 16)", "(15 15)"),
+        ("(neg (sub 0 9223372036854775807))", "9223372036854775807"),
+    ];
+
+    for (src, result) in tests {
+        assert_eq!(
+            parse_eval(src, &env).unwrap().to_string(),
+            *result,
+            "{}",
+            src
+        );
+    }
+
+    let errors = &[
+        ("(div 1 0)", "[div] division by zero"),
+        ("(rem 1 0)", "[rem] division by zero"),
+        ("(mod 1 0)", "[mod] division by zero"),
+        ("(mul 9223372036854775807 2)", "[mul] integer overflow"),
+        ("(add 9223372036854775807 1)", "[add] integer overflow"),
+        ("(min)", "[min] expected at least 1 argument, got 0"),
+        (
+            "(max 1 'a)",
+            "[max] expected an integer for argument 2, got a",
+        ),
+    ];
+
+    for (src, message) in errors {
+        let err = parse_eval(src, &env).unwrap_err();
+        assert_eq!(err.to_string(), *message, "{}", src);
+    }
+    assert!(parse_eval("(abs (sub (sub 0 9223372036854775807) 1))", &env).is_err());
+
+    // std `pow` is the native one, taking the base first
+    let pow = |src| parse_eval(src, &base_env).map(|v| v.to_string());
+    assert_eq!(pow("(pow 2 10)").unwrap(), "1024");
+    assert_eq!(pow("(pow 5 0)").unwrap(), "1");
+    assert_eq!(
+        pow("(pow 2 64)").unwrap_err().to_string(),
+        "[pow] integer overflow"
+    );
+    assert_eq!(
+        pow("(pow 2 -1)").unwrap_err().to_string(),
+        "[pow] negative exponent"
+    );
+    assert_eq!(parse_eval("(pow 10 2)", &env).unwrap().to_string(), "100");
+}
+
+#[test]
+fn builtin_errors() {
+    let env = LispEnv::default();
+
+    let errors = &[
+        (
+            "(add 1 'x)",
+            "[add] expected an integer for argument 2, got x",
+        ),
+        ("(cdr '(1) '(2))", "[cdr] expected 1 argument, got 2"),
+        ("(cdr 5)", "[cdr] expected a list for argument 1, got 5"),
+        (
+            "(sub 1 'a)",
+            "[sub] expected an integer for argument 2, got a",
+        ),
+        ("(cons 1 2)", "[cons] expected a list for argument 2, got 2"),
+        (
+            "(gensym 1)",
+            "[gensym] expected a symbol for argument 1, got 1",
+        ),
+        ("(if true 2)", "[if] expected 3 arguments, got 2"),
+        ("(let 5 1)", "[let] expected a list of bindings, got 5"),
+        ("(quote)", "[quote] expected 1 argument, got 0"),
+        (
+            "(gensym 'a 'b)",
+            "[gensym] expected 0 to 1 arguments, got 2",
+        ),
+    ];
+    for (src, message) in errors {
+        let err = parse_eval(src, &env).unwrap_err();
+        assert_eq!(err.to_string(), *message, "{}", src);
+    }
+
+    // a lazy builtin under another name is still a special form
+    let src = "(let '((myif if)) (myif true 1 (car 5)))";
+    let parsed = parse_string(src).unwrap();
+    assert_eq!(env.eval(&parsed).unwrap(), LispValue::Integer(1));
+    let reduced = env.reduce(&parsed).unwrap();
+    assert_eq!(env.eval(&reduced).unwrap(), LispValue::Integer(1));
+}
+
+#[test]
+fn comparison_operators() {
+    let env = LispEnv::default();
+
+    let tests = &[
+        ("(< 1 2 3)", true),
+        ("(< 1 3 2)", false),
+        ("(< 1 1)", false),
+        ("(<= 1 1 2)", true),
+        ("(> 3 2 1)", true),
+        ("(>= 3 3 4)", false),
+        ("(< 5)", true),
+        ("(= 2 2 2)", true),
+        ("(= 2 2 3)", false),
+        ("(= '(1 a) '(1 a))", true),
+        ("(!= 1 2 1)", true),
+        ("(!= 1 1)", false),
+        ("(< 'apple 'banana 'cherry)", true),
+        ("(>= 'b 'a 'a)", true),
+        ("(lt 1 2 3)", true),
+        ("(gt 1 2)", false),
+    ];
+    for (src, result) in tests {
+        let parsed = parse_string(src).unwrap();
+        assert_eq!(
+            env.eval(&parsed).unwrap(),
+            LispValue::Bool(*result),
+            "{}",
+            src
+        );
+        let reduced = env.reduce(&parsed).unwrap();
+        assert_eq!(
+            env.eval(&reduced).unwrap(),
+            LispValue::Bool(*result),
+            "{}",
+            src
+        );
+    }
+
+    let errors = &[
+        ("(< 1 'a)", "[<] can't compare 1 with a"),
+        ("(gt '(1) 2)", "[>] can't compare (1) with 2"),
+        ("(<=)", "[<=] expected at least 1 argument, got 0"),
+    ];
+    for (src, message) in errors {
+        let err = parse_eval(src, &env).unwrap_err();
+        assert_eq!(err.to_string(), *message, "{}", src);
+    }
+}
+
+#[test]
+fn short_circuit_forms() {
+    let base_env = LispEnv::default();
+
+    let env = get_std(&base_env).unwrap();
+
+    use LispValue::*;
+
+    // `(car 5)` is an error, so it mustn't be evaluated
+    let tests = &[
+        ("(and)", Bool(true)),
+        ("(and 1 2 3)", Integer(3)),
+        ("(and 1 () (car 5))", List(vec![].into())),
+        ("(and 1 false (car 5))", Bool(false)),
+        ("(or)", Bool(false)),
+        ("(or () false)", Bool(false)),
+        ("(or () 'a (car 5))", Symbol("a".into())),
+        ("(or false '(1))", parse_eval("'(1)", &env).unwrap()),
+        ("(when '(1) 1 2)", Integer(2)),
+        ("(when () (car 5))", List(vec![].into())),
+        ("(unless () 1 2)", Integer(2)),
+        ("(unless 0 (car 5))", List(vec![].into())),
+        ("(cond (() (car 5)) ((lt 1 2) 'yes))", Symbol("yes".into())),
+        ("(cond (false 1) (else 2 3))", Integer(3)),
+        ("(cond (false 1) (7))", Integer(7)),
+        ("(cond (false 1))", List(vec![].into())),
+        (
+            "(let '((x 4)) (cond ((lt x 3) 'small) ((lt x 5) 'medium) (else 'big)))",
+            Symbol("medium".into()),
+        ),
+        ("(let '((else false)) (cond (else 1) (true 2)))", Integer(1)),
+    ];
+
+    for (src, result) in tests {
+        let parsed = parse_string(src).unwrap();
+        assert_eq!(env.eval(&parsed).unwrap(), *result, "{}", src);
+        let reduced = env.reduce(&parsed).unwrap();
+        assert_eq!(env.eval(&reduced).unwrap(), *result, "reduced {}", src);
+    }
+}
+
+#[test]
+fn truthiness() {
+    let base_env = LispEnv::default();
+
+    let env = get_std(&base_env).unwrap();
+
+    use LispValue::*;
+
+    let tests = &[
+        ("(if () 'yes 'no)", Symbol("no".into())),
+        ("(if '(()) 'yes 'no)", Symbol("yes".into())),
+        ("(if 0 'yes 'no)", Symbol("yes".into())),
+        ("(if 'false 'yes 'no)", Symbol("yes".into())),
+        ("(not ())", Bool(true)),
+        ("(not 0)", Bool(false)),
+        ("(let '((l '(1 2))) (if l (car l) 'empty))", Integer(1)),
+        (
+            "(let '((len (fn len (l) (if l (add 1 (len (cdr l))) 0)))) (len '(a b c)))",
+            Integer(3),
+        ),
+    ];
+
+    for (src, result) in tests {
+        let parsed = parse_string(src).unwrap();
+        assert_eq!(env.eval(&parsed).unwrap(), *result, "{}", src);
+        let reduced = env.reduce(&parsed).unwrap();
+        assert_eq!(env.eval(&reduced).unwrap(), *result, "reduced {}", src);
+    }
+
+    // every conditional agrees with `truthyP`
+    for value in ["()", "false", "true", "0", "'a", "'(())"] {
+        let expected = parse_eval(&format!("(truthyP {})", value), &env).unwrap();
+        let conditions = [
+            format!("(if {} true false)", value),
+            format!("(cond ({} true) (else false))", value),
+            format!("(not (not {}))", value),
+            format!("(when {} true)", value),
+        ];
+        for src in conditions {
+            let result = parse_eval(&src, &env).unwrap().is_truthy();
+            assert_eq!(Bool(result), expected, "{}", src);
+        }
+    }
+}
+
+#[test]
+fn sequencing() {
+    let base_env = LispEnv::default();
+
+    let env = get_std(&base_env).unwrap();
+
+    use LispValue::*;
+
+    let tests = &[
+        ("(do)", List(vec![].into())),
+        ("(do 1 2 3)", Integer(3)),
+        ("(begin 'a 'b)", Symbol("b".into())),
+        ("(if true (do 1 2) 3)", Integer(2)),
+        ("((fn (x) (add x 1) (mul x 2)) 5)", Integer(10)),
+        ("((fn f :clauses ((x) x x) ((x y) x y)) 1 2)", Integer(2)),
+        ("((fn ((a b)) a b) '(1 2))", Integer(2)),
+        ("(let '((x 3)) x (add x 1))", Integer(4)),
+        (
+            "((macro (a) (list 'quote a) (list 'add a 1)) 5)",
+            Integer(6),
+        ),
+        // a param or local named `do` doesn't take over the body
+        ("((fn (do) (add do 1) do) 3)", Integer(3)),
+        ("((fn (x) (def do 2) x do) 1)", Integer(2)),
+        ("(let '((do 4)) do (add do 1))", Integer(5)),
+    ];
+
+    for (src, result) in tests {
+        let parsed = parse_string(src).unwrap();
+        assert_eq!(env.eval(&parsed).unwrap(), *result, "{}", src);
+        let reduced = env.reduce(&parsed).unwrap();
+        assert_eq!(env.eval(&reduced).unwrap(), *result, "reduced {}", src);
+        let expanded = env.macro_eval(&parsed).unwrap();
+        assert_eq!(env.eval(&expanded).unwrap(), *result, "expanded {}", src);
+    }
+
+    // only the last form's value is kept, but the others still run
+    assert!(parse_eval("(do (car 5) 1)", &env).is_err());
+
+    // a macro can build a lambda with a body of several forms
+    let env = env
+        .new_inner_from_pairs(
+            &parse_string("((twice (syntax-rules () ((_ x body) (fn (x) body body)))))").unwrap(),
+        )
+        .unwrap();
+    let result = parse_macro_pass_and_eval("((twice y (add y 1)) 4)", &env).unwrap();
+    assert_eq!(result, Integer(5));
+}
+
+#[test]
+fn let_forms() {
+    let base_env = LispEnv::default();
+
+    let std_env = get_std(&base_env).unwrap();
+
+    let env = std_env
+        .new_inner_from_pairs(
+            &parse_string("((myOr (syntax-rules () ((_ a b) (let* ((t a)) (if t t b))))))")
+                .unwrap(),
+        )
+        .unwrap();
+
+    use LispValue::*;
+
+    let tests = &[
+        ("(let ((x 1) (y 2)) (add x y))", Integer(3)),
+        ("(let '((x 1)) x)", Integer(1)),
+        ("(let (list (list 'x 5)) x)", Integer(5)),
+        ("(let () 7)", Integer(7)),
+        ("(let ((x 1)) (let ((x 2) (y x)) y))", Integer(1)),
+        ("(let* ((x 2) (y (mul x 3))) y)", Integer(6)),
+        ("(let* (((a b) '(1 2)) (c (add a b))) c)", Integer(3)),
+        (
+            "(letrec ((ev (fn (n) (if (eq n 0) true (od (sub n 1)))))
+                      (od (fn (n) (if (eq n 0) false (ev (sub n 1))))))
+                (ev 10))",
+            Bool(true),
+        ),
+        // the lambdas keep seeing each other once they are out of the `letrec`
+        (
+            "((let ((od (fn (n) 'outer)))
+                (letrec ((ev (fn (n) (if (eq n 0) true (od (sub n 1)))))
+                         (od (fn (n) (if (eq n 0) false (ev (sub n 1))))))
+                  ev))
+              3)",
+            Bool(false),
+        ),
+        // even when they are called where a sibling's name means something else
+        (
+            "(let ((ev (letrec ((ev (fn (n) (if (eq n 0) true (od (sub n 1)))))
+                                (od (fn (n) (if (eq n 0) false (ev (sub n 1))))))
+                         ev)))
+               (let ((od (fn (n) 'shadowed)))
+                 (list (if (ev 3) 1 0) (if (ev 4) 1 0))))",
+            parse_eval("'(0 1)", &env).unwrap(),
+        ),
+        (
+            "((fn (n) (def sq (mul n n)) (def twice (fn (x) (add x x))) (twice sq)) 3)",
+            Integer(18),
+        ),
+        (
+            "(do (def fact (fn (n) (if (eq n 0) 1 (mul n (fact (sub n 1)))))) (fact 5))",
+            Integer(120),
+        ),
+        (
+            "(let ((x 1)) (def y (add x 1)) (list x y))",
+            parse_eval("'(1 2)", &env).unwrap(),
+        ),
+        ("(let ((t 5)) (myOr false t))", Integer(5)),
+    ];
+
+    for (src, result) in tests {
+        let parsed = parse_string(src).unwrap();
+        assert_eq!(env.eval(&parsed).unwrap(), *result, "{}", src);
+        let reduced = env.reduce(&parsed).unwrap();
+        assert_eq!(env.eval(&reduced).unwrap(), *result, "reduced {}", src);
+        let expanded = env.macro_eval(&parsed).unwrap();
+        assert_eq!(env.eval(&expanded).unwrap(), *result, "expanded {}", src);
+    }
+
+    let err = parse_eval("(def x 1)", &env).unwrap_err();
+    assert_eq!(
+        err.to_string(),
+        "[def] can only be used among the forms of a body"
+    );
+}
+
+#[test]
+fn list_library() {
+    let base_env = LispEnv::default();
+
+    let env = get_std(&base_env).unwrap();
+
+    let tests = &[
+        ("(length '(a b c))", "3"),
+        ("(length ())", "0"),
+        ("(nth '(a b c) 1)", "b"),
+        ("(take 2 '(1 2 3))", "(1 2)"),
+        ("(take 5 '(1 2))", "(1 2)"),
+        ("(drop 2 '(1 2 3))", "(3)"),
+        ("(drop 5 '(1 2))", "()"),
+        ("(reverse '(1 2 3))", "(3 2 1)"),
+        ("(append '(1) () '(2 3))", "(1 2 3)"),
+        ("(append)", "()"),
+        ("(range 4)", "(0 1 2 3)"),
+        ("(range 2 5)", "(2 3 4)"),
+        ("(range 10 0 -3)", "(10 7 4 1)"),
+        ("(range 5 2)", "()"),
+        ("(map (fn (x) (mul x x)) '(1 2 3))", "(1 4 9)"),
+        ("(map add '(1 2 3) '(10 20))", "(11 22)"),
+        ("(map car '((a 1) (b 2)))", "(a b)"),
+        ("(filter (fn (x) (gt x 1)) '(1 2 3))", "(2 3)"),
+        ("(filter (fn (l) l) '(() (1) () (2)))", "((1) (2))"),
+        ("(fold (fn (acc x) (cons x acc)) () '(1 2 3))", "(3 2 1)"),
+        ("(fold sub 10 '(1 2))", "7"),
+        ("(reduce add '(1 2 3 4))", "10"),
+        ("(reduce add 100 '(1 2))", "103"),
+        ("(zip '(1 2 3) '(a b))", "((1 a) (2 b))"),
+        ("(sort '(3 1 2))", "(1 2 3)"),
+        ("(sort '(pear apple fig))", "(apple fig pear)"),
+        ("(sort > '(3 1 2))", "(3 2 1)"),
+        // the sort is stable
+        (
+            "(sort (fn (a b) (lt (car a) (car b))) '((2 a) (1 b) (2 c) (1 d)))",
+            "((1 b) (1 d) (2 a) (2 c))",
+        ),
+        ("(assoc 'b '((a 1) (b 2) (b 3)))", "(b 2)"),
+        ("(assoc 'z '((a 1)))", "()"),
+        ("(firsts '((a 1) (b 2)))", "(a b)"),
+        ("(seconds '((a 1) (b 2)))", "(1 2)"),
+        ("(apply add (map (fn (x) (mul x 2)) (range 1 4)))", "12"),
+    ];
+
+    for (src, result) in tests {
+        let expected = parse_string(result).unwrap();
+        let parsed = parse_string(src).unwrap();
+        assert_eq!(env.eval(&parsed).unwrap(), expected, "{}", src);
+        let reduced = env.reduce(&parsed).unwrap();
+        assert_eq!(env.eval(&reduced).unwrap(), expected, "reduced {}", src);
+    }
+
+    let errors = &[
+        ("(nth '(a) 3)", "[nth] no element 3 in a list of 1"),
+        (
+            "(take -1 '(a))",
+            "[take] expected a non-negative integer for argument 1, got -1",
+        ),
+        ("(range 0 5 0)", "[range] the step can't be 0"),
+        (
+            "(reduce add ())",
+            "[reduce] can't reduce an empty list without an initial value",
+        ),
+        ("(sort '(1 a))", "[sort] can't compare a with 1"),
+        (
+            "(map (fn (x) (car x)) '(1))",
+            "[car] expected a list for argument 1, got 1",
+        ),
+    ];
+    for (src, message) in errors {
+        let err = parse_eval(src, &env).unwrap_err();
+        assert_eq!(err.to_string(), *message, "{}", src);
+    }
+}
+
 #[test]
 fn reduce_vs_eval() {
     let base_env = LispEnv::default();
@@ -144,11 +1307,62 @@ fn reduce_vs_eval() {
     for (src, result) in tests {
         let parsed = parse_string(src).unwrap();
         let eval_result = env.eval(&parsed).unwrap();
-        let reduce_result = env.reduce(&parsed).unwrap();
+        let reduce_result = env.eval(&env.reduce(&parsed).unwrap()).unwrap();
         assert_eq!(eval_result, *result, "!!! eval !!!");
         assert_eq!(reduce_result, *result, "!!! reduce !!!");
         assert_eq!(eval_result, reduce_result, "!!! eval vs reduce !!!");
     }
+
+    // free variables are left in the residual program, to be filled in later
+    let free_env = env
+        .new_inner_from_pairs(&parse_string("((x 5) (y 7) (l '((1 2) (3 4))))").unwrap())
+        .unwrap();
+
+    let tests = &[
+        ("(add x (mul 2 3))", Some("(add x 6)")),
+        ("(if (lt 1 2) x (fib 20))", Some("x")),
+        ("(if (lt x 2) (fib 10) (sub y 1))", None),
+        ("(id (add x 1))", None),
+        ("(let '((a x) (b 2)) (add a b))", None),
+        ("(firsts l)", None),
+        ("(truthyP l)", None),
+        ("(longOr y (car l))", None),
+        ("(unsafe (spookyAdd x 1))", None),
+        ("((fn (z) (add z x)) 3)", Some("(add 3 x)")),
+        ("(eval (list 'add x 1))", None),
+    ];
+
+    for (src, residual) in tests {
+        let parsed = parse_string(src).unwrap();
+        let reduced = env.reduce(&parsed).unwrap();
+        if let Some(residual) = residual {
+            assert_eq!(reduced, parse_string(residual).unwrap(), "{}", src);
+        }
+        assert_eq!(
+            free_env.eval(&reduced).unwrap(),
+            free_env.eval(&parsed).unwrap(),
+            "{} => {}",
+            src,
+            reduced
+        );
+    }
+
+    // `include` reads its arguments as written, so they aren't reduced
+    let readable_env = env.new_capable_env(capability::Capabilities::none().allow_read("./lisb"));
+    for src in [
+        "(unsafe (include std x))",
+        "(let ((fib 7)) (unsafe (include 'std (fib 10))))",
+    ] {
+        let parsed = parse_string(src).unwrap();
+        let reduced = readable_env.reduce(&parsed).unwrap();
+        assert_eq!(
+            format!("{:?}", readable_env.eval(&reduced)),
+            format!("{:?}", readable_env.eval(&parsed)),
+            "{} => {}",
+            src,
+            reduced
+        );
+    }
     /*
         (longOr (fn (x y) (if (truthyP x) x y)))
 
@@ -158,6 +1372,109 @@ fn reduce_vs_eval() {
     */
 }
 
+#[test]
+fn fuel_limit() {
+    use budget::Budget;
+    use eval::EvalError;
+    use std::time::Duration;
+
+    let base_env = LispEnv::default();
+
+    let env = get_std(&base_env).unwrap();
+
+    let fueled = env.new_budgeted_env(Budget::new().with_fuel(1000));
+    assert_eq!(
+        parse_eval("(fib 5)", &fueled).unwrap(),
+        LispValue::Integer(5)
+    );
+    assert!(fueled.budget().unwrap().remaining_fuel().unwrap() < 1000);
+
+    let fueled = env.new_budgeted_env(Budget::new().with_fuel(1000));
+    let err = fueled.eval(&parse_string("(fib 40)").unwrap()).unwrap_err();
+    assert!(matches!(err, EvalError::OutOfFuel), "{}", err);
+
+    let fueled = env.new_budgeted_env(Budget::new().with_fuel(1000));
+    let err = fueled
+        .reduce(&parse_string("(fib 40)").unwrap())
+        .unwrap_err();
+    assert!(matches!(err, EvalError::OutOfFuel), "{}", err);
+
+    let timed = env.new_budgeted_env(Budget::new().with_timeout(Duration::from_millis(50)));
+    let err = timed.eval(&parse_string("(fib 40)").unwrap()).unwrap_err();
+    assert!(matches!(err, EvalError::DeadlineExceeded), "{}", err);
+}
+
+#[test]
+fn depth_limit() {
+    use budget::Budget;
+    use eval::EvalError;
+
+    let env = LispEnv::default();
+    let endless = parse_string("((fn f (n) (add 1 (f n))) 1)").unwrap();
+
+    // recursion that never bottoms out stops at the depth limit, however much
+    // fuel and memory are left, rather than overflowing the host's stack
+    let budget = Budget::new()
+        .with_fuel(1_000_000)
+        .with_memory_limit(1 << 30);
+    let err = env.new_budgeted_env(budget).eval(&endless).unwrap_err();
+    assert!(matches!(err, EvalError::TooDeep), "{}", err);
+
+    let shallow = env.new_budgeted_env(Budget::new().with_max_depth(50));
+    let err = shallow.eval(&endless).unwrap_err();
+    assert!(matches!(err, EvalError::TooDeep), "{}", err);
+
+    // leaving a level gives it back
+    let src = "((fn f (n) (if (eq n 0) 0 (add 1 (f (sub n 1))))) 10)";
+    for _ in 0..10 {
+        assert_eq!(parse_eval(src, &shallow).unwrap(), LispValue::Integer(10));
+    }
+}
+
+#[test]
+fn memory_limit() {
+    use budget::Budget;
+    use eval::EvalError;
+
+    let env = LispEnv::default();
+
+    let grow = |n| {
+        format!(
+            "(let '((grow (fn (n l) (if (eq n 0) l (grow (sub n 1) (cons n l)))))) (grow {} ()))",
+            n
+        )
+    };
+
+    let limited = env.new_budgeted_env(Budget::new().with_memory_limit(1 << 16));
+    let list = parse_eval(&grow(10), &limited).unwrap();
+    assert_eq!(list.get_list().unwrap().len(), 10);
+    assert!(limited.budget().unwrap().allocated().unwrap() > 0);
+
+    let limited = env.new_budgeted_env(Budget::new().with_memory_limit(1 << 16));
+    let err = limited
+        .eval(&parse_string(&grow(1000)).unwrap())
+        .unwrap_err();
+    assert!(matches!(err, EvalError::OutOfMemory), "{}", err);
+
+    // looking into a consed list walks it rather than copying it out, so
+    // this only pays for its cells
+    let walk = "(let '((grow (fn (n l) (if (eq n 0) l
+        (match (cons n l) ((x & rest)
+            (grow (sub x 1) (cons (nth rest (sub (length rest) 1)) (drop 0 (cons x rest))))))))))
+        (length (grow 40 '(0))))";
+    let limited = env.new_budgeted_env(Budget::new().with_memory_limit(1 << 16));
+    assert_eq!(parse_eval(walk, &limited).unwrap(), LispValue::Integer(81));
+
+    // applying one still needs it side by side, which is charged for
+    let allocated = |src: &str| {
+        let limited = env.new_budgeted_env(Budget::new().with_memory_limit(1 << 16));
+        parse_eval(src, &limited).unwrap();
+        limited.budget().unwrap().allocated().unwrap()
+    };
+    let applied = allocated(&format!("(apply add {})", grow(100)));
+    assert!(applied >= allocated(&grow(100)) + 100 * std::mem::size_of::<LispValue>());
+}
+
 #[test]
 fn scan_math_test() {
     let src = "(add
@@ -175,3 +1492,222 @@ fn scan_math_test() {
 
     assert_eq!(tokens.len(), 37);
 }
+
+#[test]
+fn scan_atoms() {
+    use scan::TokenPayload::*;
+
+    let atoms = |src: &str| {
+        scan::Scanner::new(src)
+            .map(|token| match token.payload {
+                Atom(atom, is_number) => Some((atom, is_number)),
+                _ => None,
+            })
+            .collect::<Vec<_>>()
+    };
+    let atom = |s: &str, is_number| Some((s.to_string(), is_number));
+
+    // anything but whitespace and parens is part of an atom
+    assert_eq!(
+        atoms("macroexpand-1 <= a*b x'y"),
+        vec![
+            atom("macroexpand-1", false),
+            atom("<=", false),
+            atom("a*b", false),
+            atom("x'y", false),
+        ]
+    );
+    assert_eq!(atoms("(f)"), vec![None, atom("f", false), None]);
+
+    // only a whole run of digits, maybe after a `-`, is a number
+    assert_eq!(
+        atoms("12 -3 - 1a 99999999999999999999"),
+        vec![
+            atom("12", true),
+            atom("-3", true),
+            atom("-", false),
+            atom("1a", false),
+            atom("99999999999999999999", true),
+        ]
+    );
+
+    // a quote only counts at the start of a token, and `*` only before `)`
+    let tokens = scan::Scanner::new("'a (* b *) *").collect::<Vec<_>>();
+    assert!(
+        matches!(&tokens[0].payload, Quote(inner) if matches!(&**inner, Atom(a, false) if a == "a"))
+    );
+    assert!(matches!(tokens[1].payload, RemarkStart));
+    assert!(matches!(tokens[3].payload, RemarkEnd));
+    assert!(matches!(&tokens[4].payload, Atom(a, false) if a == "*"));
+
+    // numbers too big for an integer are an error rather than a symbol
+    assert!(parse_string("99999999999999999999").is_err());
+    assert_eq!(parse_string("-3").unwrap(), LispValue::Integer(-3));
+}
+
+#[test]
+fn persistent_lists() {
+    let env = LispEnv::default();
+
+    let tests = &[
+        ("(cons 1 (cons 2 (cons 3 ())))", "(1 2 3)"),
+        ("(cdr (cons 0 '(1 2 3)))", "(1 2 3)"),
+        ("(car (cdr (cdr (cons 0 '(1 2 3)))))", "2"),
+        ("(cdr (cdr (cdr '(1 2 3))))", "()"),
+        ("(length (cons 0 (cdr '(1 2 3))))", "3"),
+        ("(map (fn (x) (mul x 2)) (cons 1 (cons 2 ())))", "(2 4)"),
+        ("(nth (cons 0 (cons 1 '(2 3))) 3)", "3"),
+        ("(reverse (cons 1 (cons 2 '(3))))", "(3 2 1)"),
+        ("(if (eq (cons 1 '(2 3)) '(1 2 3)) 1 0)", "1"),
+        ("(if (eq (cdr '(0 1 2)) (cons 1 (cons 2 ()))) 1 0)", "1"),
+    ];
+
+    for (src, result) in tests {
+        let expected = parse_string(result).unwrap();
+        assert_eq!(parse_eval(src, &env).unwrap(), expected, "{}", src);
+    }
+
+    // consing onto a list shares it rather than copying it
+    let tail = parse_eval("'(2 3)", &env).unwrap();
+    let consed = LispValue::cons(&LispValue::Integer(1), &tail).unwrap();
+    assert_eq!(consed.tail().unwrap(), tail);
+    assert_eq!(consed.get_list().unwrap().len(), 3);
+}
+
+// builds a list of pairs with cons and walks it with a recursive car/cdr
+// `firsts`, giving the time, the steps and the bytes allocated for each size
+fn firsts_runs(sizes: &[i64]) -> Vec<(std::time::Duration, u64, u64)> {
+    use budget::Budget;
+    use std::time::Instant;
+
+    let env = LispEnv::default();
+    let mut runs = vec![];
+    for n in sizes {
+        let src = format!(
+            "(let ((pairs (fn pairs (n l) (if (eq n 0) l (pairs (sub n 1) (cons (list n n) l)))))
+                  (firsts (fn firsts (l) (if l (cons (car (car l)) (firsts (cdr l))) ()))))
+               (length (firsts (pairs {} ()))))",
+            n
+        );
+        let parsed = parse_string(&src).unwrap();
+        let budgeted = env.new_budgeted_env(
+            Budget::new()
+                .with_fuel(u64::MAX)
+                .with_memory_limit(usize::MAX)
+                .with_max_depth(usize::MAX),
+        );
+        let start = Instant::now();
+        assert_eq!(budgeted.eval(&parsed).unwrap(), LispValue::Integer(*n));
+        let elapsed = start.elapsed();
+        let budget = budgeted.budget().unwrap();
+        let steps = u64::MAX - budget.remaining_fuel().unwrap();
+        let allocated = budget.allocated().unwrap() as u64;
+        println!(
+            "firsts on {} elements: {:?}, {} steps, {} bytes",
+            n, elapsed, steps, allocated
+        );
+        runs.push((elapsed, steps, allocated));
+    }
+    runs
+}
+
+#[test]
+fn firsts_scaling() {
+    // recursing once per element doesn't need a bigger stack than any other
+    // test, and each doubling adds about as much work as the one before it
+    let runs = firsts_runs(&[2500, 5000, 10000]);
+    let linear = |a: u64, b: u64, c: u64| c - b <= (b - a) * 2 + (b - a) / 10;
+    assert!(linear(runs[0].1, runs[1].1, runs[2].1), "{:?}", runs);
+    assert!(linear(runs[0].2, runs[1].2, runs[2].2), "{:?}", runs);
+}
+
+// cargo test --release firsts_benchmark -- --ignored --nocapture
+#[test]
+#[ignore]
+fn firsts_benchmark() {
+    use std::time::Duration;
+
+    // four times the elements should take nowhere near sixteen times as long
+    let runs = firsts_runs(&[2500, 5000, 10000]);
+    assert!(
+        runs[2].0 < runs[0].0 * 10 + Duration::from_millis(50),
+        "{:?}",
+        runs
+    );
+}
+
+#[test]
+fn interned_symbols() {
+    use symbol::SymbolValue;
+
+    let a = SymbolValue::new("interned-name");
+    let b = SymbolValue::from(String::from("interned-name"));
+    assert_eq!(a, b);
+    assert!(std::ptr::eq(a.as_str(), b.as_str()));
+    assert_ne!(a, SymbolValue::new("other-name"));
+
+    // copies share a site, while each symbol made from a name gets its own
+    let c = a;
+    assert_eq!(c.site(), a.site());
+    assert_ne!(a.site(), b.site());
+
+    // looking a name up doesn't intern it
+    let env = LispEnv::default();
+    assert!(env.get("never-written-anywhere").is_none());
+    assert!(SymbolValue::find("never-written-anywhere").is_none());
+    assert!(env.get("car").is_some());
+
+    let list = parse_string("(x x)").unwrap();
+    let [x1, x2] = list.get_list().unwrap() else {
+        panic!("{}", list)
+    };
+    assert_eq!(x1, x2);
+    assert_eq!(x1.get_symbol(), Some("x"));
+    assert_eq!(
+        parse_eval("(let ((sym 'abc)) (if (eq sym 'abc) 1 0))", &env).unwrap(),
+        LispValue::Integer(1)
+    );
+
+    // fresh symbols are told apart by their site, without interning a name each
+    let (f1, f2) = (SymbolValue::fresh("tmp"), SymbolValue::fresh("tmp"));
+    assert_ne!(f1, f2);
+    let copied = f1;
+    assert_eq!(copied, f1);
+    assert_ne!(f1, SymbolValue::new(&f1.to_string()));
+    assert!(SymbolValue::find(&f2.to_string()).is_none());
+    assert!(std::ptr::eq(f1.as_str(), SymbolValue::new("tmp%").as_str()));
+
+    // and compared by that too, rather than by the name they share
+    assert!(f1 < f2);
+    assert!(SymbolValue::new("tmp") < f1);
+    let src = "(let ((a (gensym 'x)) (b (gensym 'x))) (list (lt a b) (lt b a) (eq (sort (list b a)) (list a b))))";
+    let ordered = parse_eval(src, &env).unwrap();
+    let expected = [true, false, true].map(LispValue::Bool);
+    assert_eq!(ordered.get_list().unwrap(), &expected[..]);
+
+    // a fresh literal in `syntax-rules` only matches itself
+    let (g1, g2) = (LispValue::Symbol(f1), LispValue::Symbol(f2));
+    let pattern = LispValue::List(vec![LispValue::Symbol("_".into()), g1.clone()].into());
+    let rules = mac::SyntaxRules::new(
+        &LispValue::List(vec![g1.clone()].into()),
+        &[
+            LispValue::List(vec![pattern, LispValue::Integer(1)].into()),
+            parse_string("((_ other) 2)").unwrap(),
+        ],
+    )
+    .unwrap();
+    assert_eq!(rules.expand(&[g1]).unwrap(), LispValue::Integer(1));
+    assert_eq!(rules.expand(&[g2]).unwrap(), LispValue::Integer(2));
+    assert_eq!(
+        parse_eval("(let ((g (gensym))) (if (eq g g) 1 0))", &env).unwrap(),
+        LispValue::Integer(1)
+    );
+
+    // names read from outside are charged for, since interning keeps them
+    let budget = budget::Budget::new().with_memory_limit(1 << 16);
+    let limited = env.new_budgeted_env(budget);
+    let name = "a-name-that-takes-up-some-room";
+    limited.charge_value(&parse_string(name).unwrap()).unwrap();
+    assert!(limited.budget().unwrap().allocated().unwrap() >= name.len());
+}
+
@@ -1,22 +1,22 @@
+pub use super::builtin::Builtin;
+use super::capability::Capability;
 use super::env::Bindings;
-use super::env::LispEnv;
 use super::eval::EvalError;
+pub use super::list::ListValue;
 pub use super::mac::MacroValue;
+use super::params::ParamList;
+pub use super::symbol::SymbolValue;
 use std::sync::Arc;
 
-pub type ListValue = Arc<[Value]>;
-
-pub type FuncValue = fn(&[Value], &LispEnv) -> Result<Value, EvalError>;
-
 #[derive(Clone)]
 pub enum Value {
     Bool(bool),
     Integer(i64),
-    Symbol(String),
+    Symbol(SymbolValue),
     List(ListValue),
     Macro(MacroValue),
-    Func(FuncValue),
-    UnsafeFunc(FuncValue),
+    Func(Builtin),
+    UnsafeFunc(UnsafeFuncValue),
     Lambda(LambdaValue),
     //~ Env(Arc<LispEnv<'static>>),
     UnsafeCall(ListValue),
@@ -26,20 +26,76 @@ pub enum Value {
 
 //~ pub struct Partial {}
 
+// a function with outside effects, which may only be called inside `unsafe`
+// and only when every capability it needs has been granted
+#[derive(Clone, Copy)]
+pub struct UnsafeFuncValue {
+    pub builtin: Builtin,
+    pub capabilities: &'static [Capability],
+}
+
 #[derive(Clone)]
 pub struct LambdaValue {
-    pub args: Arc<Value>,
-    pub body: Arc<Value>,
+    // the name the lambda can call itself by inside its bodies
+    pub name: Option<SymbolValue>,
+    // a params list and a body for each arity
+    pub clauses: Arc<[(Value, Value)]>,
     pub closure: Bindings,
+    // lambdas bound together with this one, by `letrec` or a run of `def`,
+    // which it can call by name
+    pub group: Arc<Bindings>,
 }
 
 impl LambdaValue {
-    pub fn new(args: Arc<Value>, body: Arc<Value>, closure: Bindings) -> LambdaValue {
+    pub fn new(
+        name: Option<SymbolValue>,
+        clauses: Arc<[(Value, Value)]>,
+        closure: Bindings,
+    ) -> LambdaValue {
         LambdaValue {
-            args,
-            body,
+            name,
+            clauses,
             closure,
+            group: Default::default(),
+        }
+    }
+
+    // the first clause that takes this many arguments. with only one clause,
+    // binding its params is what reports a wrong number of them
+    pub fn clause(&self, count: usize) -> Result<(ParamList, &Value), EvalError> {
+        if let [(params, body)] = &self.clauses[..] {
+            return Ok((ParamList::parse(params)?, body));
+        }
+        for (params, body) in self.clauses.iter() {
+            let params = ParamList::parse(params)?;
+            if params.accepts(count) {
+                return Ok((params, body));
+            }
+        }
+        Err(EvalError::String(format!(
+            "no clause of {} takes {} arguments",
+            self.name.as_deref().unwrap_or("the lambda"),
+            count
+        )))
+    }
+
+    // the closure, along with the lambda's own name and its group
+    pub fn scope(&self) -> Bindings {
+        let mut bindings = self.closure.clone();
+        for (name, value) in self.group.iter() {
+            let value = match value {
+                Value::Lambda(lambda) => Value::Lambda(LambdaValue {
+                    group: self.group.clone(),
+                    ..lambda.clone()
+                }),
+                value => value.clone(),
+            };
+            bindings.insert(*name, value);
         }
+        if let Some(name) = self.name {
+            bindings.insert(name, Value::Lambda(self.clone()));
+        }
+        bindings
     }
 }
 
@@ -50,39 +106,28 @@ impl Value {
         Ok(match self {
             Bool(b) => Bool(*b),
             Integer(i) => Integer(*i),
-            Symbol(s) => Symbol(s.clone()),
+            Symbol(s) => Symbol(*s),
             List(arc) => List(arc.clone()),
             Macro(m) => Macro(m.clone()),
             Func(f) => Func(*f),
             UnsafeFunc(f) => UnsafeFunc(*f),
             Lambda(lam) => Lambda(lam.clone()),
 
-            UnsafeCall(_) => todo!(),
+            UnsafeCall(call) => UnsafeCall(call.clone()),
         })
     }
 
     pub fn cons(head: &Value, tail: &Value) -> Option<Value> {
-        //~ // TODO: fancy iterator chain version
-        //~ Some(iter::once(x).chain(arc.iter()).collect())
-        let mut list = vec![head.clone()];
-        list.extend_from_slice(tail.get_list()?);
-        Some(Value::List(list.into()))
+        Some(Value::List(tail.get_list_value()?.cons(head.clone())))
     }
 
     pub fn head(&self) -> Option<Value> {
-        let list = self.get_list()?;
-        if list.is_empty() {
-            return Some(Value::nil());
-        }
-        Some(list[0].clone())
+        let list = self.get_list_value()?;
+        Some(list.first().cloned().unwrap_or_else(Value::nil))
     }
 
     pub fn tail(&self) -> Option<Value> {
-        let list = self.get_list()?;
-        if list.is_empty() {
-            return Some(Value::nil());
-        }
-        Some(Value::List(list[1..].into()))
+        Some(Value::List(self.get_list_value()?.rest()))
     }
 
     pub fn get_list(&self) -> Option<&[Value]> {
@@ -92,12 +137,27 @@ impl Value {
         }
     }
 
+    // the list itself, for taking apart or adding to without copying it
+    pub fn get_list_value(&self) -> Option<&ListValue> {
+        match self {
+            Value::List(l) => Some(l),
+            _ => None,
+        }
+    }
+
+    // describe calling an unsafe function on already evaluated arguments
+    pub fn unsafe_call(func: &Value, args: Vec<Value>) -> Value {
+        let mut call = vec![func.clone()];
+        call.extend(args);
+        Value::UnsafeCall(call.into())
+    }
+
     pub fn quoted(v: Value) -> Value {
         Value::List(vec![Value::Symbol("quote".into()), v].into())
     }
 
     pub fn nil() -> Value {
-        Value::List(Arc::new([]))
+        Value::List(ListValue::default())
     }
 
     pub fn get_int(&self) -> Option<i64> {
@@ -107,13 +167,32 @@ impl Value {
         }
     }
 
-    pub fn get_symbol(&self) -> Option<&String> {
+    pub fn get_symbol(&self) -> Option<&str> {
         match self {
             Value::Symbol(s) => Some(s),
             _ => None,
         }
     }
 
+    pub fn get_symbol_value(&self) -> Option<SymbolValue> {
+        match self {
+            Value::Symbol(s) => Some(*s),
+            _ => None,
+        }
+    }
+
+    // symbols like `:width` name keyword arguments and evaluate to themselves
+    pub fn is_keyword(&self) -> bool {
+        self.get_symbol()
+            .is_some_and(|s| s.len() > 1 && s.starts_with(':'))
+    }
+
+    // what conditions test for: everything but `false` and `()` counts as
+    // true, so `0`, symbols and non-empty lists do too
+    pub fn is_truthy(&self) -> bool {
+        !matches!(self, Value::Bool(false)) && !self.is_nil()
+    }
+
     pub fn get_bool(&self) -> Option<bool> {
         match self {
             Value::Bool(b) => Some(*b),
@@ -178,6 +257,20 @@ impl Value {
             _ => false,
         }
     }
+
+    // approximate number of bytes this value owns outside of itself,
+    // used to charge evaluation budgets for freshly built data
+    pub fn heap_size(&self) -> usize {
+        match self {
+            Value::List(list) => {
+                list.len() * std::mem::size_of::<Value>()
+                    + list.iter().map(Value::heap_size).sum::<usize>()
+            }
+            // the name, which interning it might have had to keep for good
+            Value::Symbol(s) => s.len(),
+            _ => 0,
+        }
+    }
 }
 
 impl PartialEq for Value {
@@ -213,6 +306,36 @@ impl PartialEq for Value {
 // Do not uncomment unless PartialEq is modified to be reflexive
 //impl Eq for Value {}
 
+impl Value {
+    // the value printed across several lines, breaking any list that doesn't
+    // fit in the given width so each of its elements gets a line of its own
+    pub fn pretty(&self, width: usize) -> String {
+        let mut out = String::new();
+        self.pretty_into(&mut out, 0, width);
+        out
+    }
+
+    fn pretty_into(&self, out: &mut String, indent: usize, width: usize) {
+        let flat = self.to_string();
+        let list = match self {
+            Value::List(list) if indent + flat.len() > width && list.len() > 1 => list,
+            _ => {
+                out.push_str(&flat);
+                return;
+            }
+        };
+
+        out.push('(');
+        list[0].pretty_into(out, indent + 1, width);
+        for v in list[1..].iter() {
+            out.push('\n');
+            out.push_str(&" ".repeat(indent + 2));
+            v.pretty_into(out, indent + 2, width);
+        }
+        out.push(')');
+    }
+}
+
 impl core::fmt::Debug for Value {
     fn fmt(&self, f: &mut core::fmt::Formatter) -> core::fmt::Result {
         write!(f, "{}", &self)
@@ -244,7 +367,16 @@ impl core::fmt::Display for Value {
                 Ok(())
             }
             Lambda(_lambda) => write!(f, "[Lambda]"),
-            UnsafeCall(_) => write!(f, "[UnsafeCall]"),
+            UnsafeCall(call) => {
+                write!(f, "[UnsafeCall")?;
+                for v in call.iter() {
+                    match v {
+                        UnsafeFunc(func) => write!(f, " {}", func.builtin.name)?,
+                        _ => write!(f, " {}", v)?,
+                    }
+                }
+                write!(f, "]")
+            }
         }
     }
 }
@@ -282,7 +414,7 @@ impl From<i64> for Value {
 
 impl From<&str> for Value {
     fn from(v: &str) -> Value {
-        Value::Symbol(v.to_string())
+        Value::Symbol(v.into())
     }
 }
 